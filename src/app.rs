@@ -1,80 +1,380 @@
-use std::{env::current_exe, fs, path::PathBuf};
+use std::{
+    env::current_exe,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
 
 use tar::Archive;
 
-use crate::shared::{embed_error::EmbedError, embed_trailer::TRAILER};
+use crate::shared::{
+    embed_error::EmbedError,
+    embed_trailer::TRAILER,
+    payload::{
+        Compression, Manifest, PackInfo, FOOTER_LEN, FORMAT_VERSION, LOCK_SUFFIX, PACK_MARKER,
+        RUNNING_SUFFIX, STAGING_SUFFIX,
+    },
+};
+use crate::temp::{lock_file, try_lock_file, with_suffix};
 pub static TRAILER_LEN: usize = TRAILER.len();
 
-pub struct App(Vec<u8>);
+pub struct App {
+    exe: File,
+    manifest: Manifest,
+}
 
 impl App {
     pub fn load() -> Result<App, EmbedError> {
         let exe_path = current_exe().map_err(|_| EmbedError("Couldn't find executable".into()))?;
-        let mut exe =
-            fs::read(&exe_path).map_err(|_| EmbedError("Unable to read executable".into()))?;
-        let is_embedded = take_trailer(&mut exe)?;
+        App::open(&exe_path)
+    }
 
-        if !is_embedded {
+    pub fn open(path: &Path) -> Result<App, EmbedError> {
+        let mut exe =
+            File::open(path).map_err(|_| EmbedError("Unable to read executable".into()))?;
+        let Some(end) = read_trailer(&mut exe)? else {
             return Err(EmbedError("Application was not compiled".into()));
-        }
-        Ok(App(exe))
+        };
+        let manifest = read_manifest(&mut exe, end)?;
+        Ok(App { exe, manifest })
+    }
+
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
     }
 
     /**
      * Read Embed data
      */
 
-    pub fn take_pack(&mut self, path: &PathBuf, cache: bool) -> Result<(), EmbedError> {
-        let data = self.take_data();
-        if cache && path.exists() {
-            return Ok(());
+    /**
+     * Unpacks into a staging directory that replaces the one at path once complete.
+     * Files the app created in the old directory are moved along, so its data survives.
+     * A directory other instances run from is used as it is, returns the lock they hold shared
+     */
+    pub fn take_pack(
+        &mut self,
+        info: &PackInfo,
+        path: &Path,
+        cache: bool,
+    ) -> Result<File, EmbedError> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|_| EmbedError(format!("Unable to create {parent:?}")))?;
+        }
+        // Another instance may be unpacking the same pack, wait for it to finish
+        let _unpacking = lock_file(&with_suffix(path, LOCK_SUFFIX), false)
+            .map_err(|_| EmbedError("Unable to lock unpack directory".into()))?;
+        let running = with_suffix(path, RUNNING_SUFFIX);
+        let lock_error = |_| EmbedError("Unable to lock running directory".into());
+        let Some(lock) = try_lock_file(&running).map_err(lock_error)? else {
+            return lock_file(&running, true).map_err(lock_error);
+        };
+        if !cache || !App::is_unpacked(path, info) {
+            self.replace_dir(info, path)?;
         }
-        App::unpack(path, data)
+        // No instance can take the lock in between, they wait for the unpack lock
+        lock.unlock().map_err(lock_error)?;
+        lock.lock_shared().map_err(lock_error)?;
+        Ok(lock)
     }
 
-    pub fn take_data(&mut self) -> Vec<u8> {
-        let len = self.take_sized::<usize>();
-        let index = self.0.len() - len;
-        let mut result = vec![0; len];
-        result.copy_from_slice(&self.0[index..]);
-        self.0.truncate(index);
-        result
+    fn replace_dir(&mut self, info: &PackInfo, path: &Path) -> Result<(), EmbedError> {
+        // Unpacked files only show up at the path once complete
+        let staging = with_suffix(path, STAGING_SUFFIX);
+        if staging.exists() {
+            fs::remove_dir_all(&staging)
+                .map_err(|_| EmbedError("Unable to remove an interrupted unpack".into()))?;
+        }
+        App::unpack(&staging, self.take_reader(info)?)?;
+        fs::write(staging.join(PACK_MARKER), &info.hash)
+            .map_err(|_| EmbedError("Unable to write pack marker".into()))?;
+        if path.exists() {
+            keep_created_files(path, &staging)
+                .map_err(|_| EmbedError(format!("Unable to keep the files created in {path:?}")))?;
+            fs::remove_dir_all(path)
+                .map_err(|_| EmbedError(format!("Unable to replace {path:?}, is it in use?")))?;
+        }
+        fs::rename(&staging, path)
+            .map_err(|_| EmbedError("Unable to move unpacked files into place".into()))
     }
 
-    pub fn take_string(&mut self) -> Result<String, EmbedError> {
-        let data = self.take_data();
-        String::from_utf8(data).map_err(|_| EmbedError("Unable to parse data to string".into()))
+    /**
+     * Checks the marker and that no packed file went missing or changed size,
+     * files created after unpacking are left alone
+     */
+    fn is_unpacked(path: &Path, info: &PackInfo) -> bool {
+        let marker = fs::read_to_string(path.join(PACK_MARKER)).unwrap_or_default();
+        marker == info.hash
+            && info.files.iter().all(|file| {
+                fs::metadata(path.join(&file.path)).is_ok_and(|md| md.len() == file.size)
+            })
     }
 
-    pub fn take_strings(&mut self) -> Result<Vec<String>, EmbedError> {
-        let len: usize = self.take_sized();
-        let mut strings = vec![];
-        for _ in 0..len {
-            strings.push(self.take_string()?);
-        }
-        Ok(strings)
+    pub fn take_reader(&mut self, info: &PackInfo) -> Result<Box<dyn Read + '_>, EmbedError> {
+        read_pack(&self.exe, info)
     }
 
-    pub fn take_sized<T: Sized>(&mut self) -> T {
-        let index = self.0.len() - size_of::<T>();
-        let result: T = unsafe { std::ptr::read(self.0[index..].as_ptr() as *const _) };
-        self.0.truncate(index);
-        result
+    /**
+     * A separate handle to the executable, for reading packs after the app is dropped
+     */
+    pub fn exe(&self) -> Result<File, EmbedError> {
+        self.exe
+            .try_clone()
+            .map_err(|_| EmbedError("Unable to read executable".into()))
     }
 
-    fn unpack(path: &PathBuf, data: Vec<u8>) -> Result<(), EmbedError> {
-        let mut archive = Archive::new(data.as_slice());
+    pub fn unpack(path: &Path, reader: impl Read) -> Result<(), EmbedError> {
+        let mut archive = Archive::new(reader);
         archive
             .unpack(path)
             .map_err(|_| EmbedError("Unable to unpack".into()))
     }
 }
 
-fn take_trailer(exe: &mut Vec<u8>) -> Result<bool, EmbedError> {
-    let index = exe.len() - TRAILER_LEN;
-    if &exe[index..] != TRAILER {
-        return Ok(false);
+pub fn read_data<'a>(mut exe: &'a File, info: &PackInfo) -> Result<impl BufRead + 'a, EmbedError> {
+    exe.seek(SeekFrom::Start(info.offset))
+        .map_err(|_| EmbedError("Embedded data is out of bounds".into()))?;
+    Ok(BufReader::new(exe.take(info.len)))
+}
+
+/**
+ * Reads the tar archive of a pack, decompressing it if needed
+ */
+pub fn read_pack<'a>(exe: &'a File, info: &PackInfo) -> Result<Box<dyn Read + 'a>, EmbedError> {
+    let data = read_data(exe, info)?;
+    Ok(match info.compression {
+        Compression::None => Box::new(data),
+        Compression::Zstd(_) => Box::new(
+            zstd::Decoder::with_buffer(data)
+                .map_err(|_| EmbedError("Unable to decompress pack".into()))?,
+        ),
+    })
+}
+
+/**
+ * Moves everything the new unpack doesn't have from the old directory into it,
+ * packed files are replaced by their unpacked version
+ */
+fn keep_created_files(old: &Path, new: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(old)? {
+        let entry = entry?;
+        let target = new.join(entry.file_name());
+        match fs::symlink_metadata(&target) {
+            Err(_) => fs::rename(entry.path(), target)?,
+            Ok(md) if md.is_dir() && entry.file_type()?.is_dir() => {
+                keep_created_files(&entry.path(), &target)?
+            }
+            Ok(_) => {}
+        }
+    }
+    Ok(())
+}
+
+/**
+ * Returns the offset at which the trailer starts, if there is one
+ */
+fn read_trailer(exe: &mut File) -> Result<Option<u64>, EmbedError> {
+    let len = exe
+        .seek(SeekFrom::End(0))
+        .map_err(|_| EmbedError("Unable to read executable".into()))?;
+    if len < TRAILER_LEN as u64 {
+        return Ok(None);
+    }
+    let index = len - TRAILER_LEN as u64;
+    if read_at(exe, index, TRAILER_LEN)? != TRAILER {
+        return Ok(None);
+    }
+    Ok(Some(index))
+}
+
+fn read_manifest(exe: &mut File, end: u64) -> Result<Manifest, EmbedError> {
+    if end < FOOTER_LEN as u64 {
+        return Err(EmbedError("Embedded payload is truncated".into()));
+    }
+    let footer_start = end - FOOTER_LEN as u64;
+    let footer = read_at(exe, footer_start, FOOTER_LEN)?;
+    let (len, version) = footer.split_at(size_of::<u64>());
+    let version = u32::from_le_bytes(version.try_into().unwrap());
+    if version != FORMAT_VERSION {
+        return Err(EmbedError(format!(
+            "Payload format version {version} is not supported by this runtime (expected {FORMAT_VERSION}), recompile the application with a matching deutron"
+        )));
+    }
+    let len = u64::from_le_bytes(len.try_into().unwrap());
+    if footer_start < len {
+        return Err(EmbedError("Embedded manifest is truncated".into()));
+    }
+    let data = read_at(exe, footer_start - len, len as usize)?;
+    serde_json::from_slice(&data).map_err(|e| EmbedError(format!("Unable to read manifest: {e}")))
+}
+
+fn read_at(exe: &mut File, offset: u64, len: usize) -> Result<Vec<u8>, EmbedError> {
+    let mut data = vec![0; len];
+    exe.seek(SeekFrom::Start(offset))
+        .and_then(|_| exe.read_exact(&mut data))
+        .map_err(|_| EmbedError("Unable to read executable".into()))?;
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, env, fs, path::PathBuf};
+
+    use super::{keep_created_files, App};
+    use crate::shared::{
+        embed_trailer::TRAILER,
+        payload::{
+            Compression, ExtractDir, Flags, Manifest, PackEntry, PackInfo, FORMAT_VERSION,
+            PACK_MARKER,
+        },
+    };
+
+    fn manifest() -> Manifest {
+        Manifest {
+            id: "app".into(),
+            command: vec!["node".into(), "./main.js".into()],
+            version: "1.2.3".into(),
+            flags: Flags::default(),
+            pack: PackInfo {
+                offset: 7,
+                len: 0,
+                compression: Compression::Zstd(3),
+                hash: "0123456789abcdef".into(),
+                files: vec![],
+            },
+            frontend: None,
+            interpreter: None,
+            extract_dir: ExtractDir::Cache,
+            env: BTreeMap::from([("KEY".into(), "value".into())]),
+            cwd: Some("server".into()),
+        }
+    }
+
+    /**
+     * Writes a binary the way the compiler does, with a runtime of a few bytes
+     */
+    fn write_payload(name: &str, manifest: &[u8], len: u64, version: u32) -> PathBuf {
+        let path = env::temp_dir().join(format!("deutron-test-{}-{name}", std::process::id()));
+        let mut data = b"runtime".to_vec();
+        data.extend_from_slice(manifest);
+        data.extend_from_slice(&len.to_le_bytes());
+        data.extend_from_slice(&version.to_le_bytes());
+        data.extend_from_slice(TRAILER);
+        fs::write(&path, data).unwrap();
+        path
+    }
+
+    #[test]
+    fn manifest_round_trip() {
+        let json = serde_json::to_vec(&manifest()).unwrap();
+        let path = write_payload("round-trip", &json, json.len() as u64, FORMAT_VERSION);
+        let app = App::open(&path);
+        fs::remove_file(&path).unwrap();
+
+        let read = app.unwrap().manifest().clone();
+        assert_eq!(read.id, "app");
+        assert_eq!(read.command, ["node", "./main.js"]);
+        assert_eq!(read.version, "1.2.3");
+        assert_eq!(read.pack.offset, 7);
+        assert_eq!(read.pack.compression, Compression::Zstd(3));
+        assert_eq!(read.extract_dir, ExtractDir::Cache);
+        assert_eq!(read.env.get("KEY").map(String::as_str), Some("value"));
+        assert_eq!(read.cwd.as_deref(), Some("server"));
+    }
+
+    #[test]
+    fn truncated_payload() {
+        let json = serde_json::to_vec(&manifest()).unwrap();
+        let path = write_payload("truncated", &json, 1 << 32, FORMAT_VERSION);
+        let app = App::open(&path);
+        fs::remove_file(&path).unwrap();
+
+        let err = app.err().unwrap();
+        assert_eq!(err.0, "Embedded manifest is truncated");
+    }
+
+    #[test]
+    fn mismatched_format_version() {
+        let json = serde_json::to_vec(&manifest()).unwrap();
+        let path = write_payload("version", &json, json.len() as u64, FORMAT_VERSION + 1);
+        let app = App::open(&path);
+        fs::remove_file(&path).unwrap();
+
+        let err = app.err().unwrap();
+        assert!(err.0.starts_with(&format!(
+            "Payload format version {} is not supported",
+            FORMAT_VERSION + 1
+        )));
+    }
+
+    #[test]
+    fn not_compiled() {
+        let path = env::temp_dir().join(format!("deutron-test-{}-plain", std::process::id()));
+        fs::write(&path, b"runtime").unwrap();
+        let app = App::open(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(app.err().unwrap().0, "Application was not compiled");
+    }
+
+    #[test]
+    fn unpacked_files() {
+        let dir = env::temp_dir().join(format!("deutron-test-{}-unpacked", std::process::id()));
+        fs::create_dir_all(dir.join("js")).unwrap();
+        fs::write(dir.join("index.html"), "<html>").unwrap();
+        fs::write(dir.join("js/app.js"), "app()").unwrap();
+        let mut info = manifest().pack;
+        info.files = vec![
+            PackEntry {
+                path: "index.html".into(),
+                size: 6,
+            },
+            PackEntry {
+                path: "js/app.js".into(),
+                size: 5,
+            },
+        ];
+        assert!(!App::is_unpacked(&dir, &info), "no marker");
+        fs::write(dir.join(PACK_MARKER), "another hash").unwrap();
+        assert!(!App::is_unpacked(&dir, &info), "marker of another pack");
+        fs::write(dir.join(PACK_MARKER), &info.hash).unwrap();
+        assert!(App::is_unpacked(&dir, &info));
+
+        fs::write(dir.join("data.db"), "created by the app").unwrap();
+        assert!(
+            App::is_unpacked(&dir, &info),
+            "created files are left alone"
+        );
+        fs::write(dir.join("js/app.js"), "changed()").unwrap();
+        assert!(!App::is_unpacked(&dir, &info), "changed size");
+        fs::remove_file(dir.join("js/app.js")).unwrap();
+        assert!(!App::is_unpacked(&dir, &info), "missing file");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn created_files_are_kept() {
+        let dir = env::temp_dir().join(format!("deutron-test-{}-keep", std::process::id()));
+        let (old, new) = (dir.join("old"), dir.join("new"));
+        for (path, data) in [
+            (old.join("main.js"), "old"),
+            (old.join("data/app.db"), "db"),
+            (old.join("js/cache/1.js"), "cached"),
+            (old.join("js/app.js"), "old"),
+            (new.join("main.js"), "new"),
+            (new.join("js/app.js"), "new"),
+        ] {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, data).unwrap();
+        }
+        keep_created_files(&old, &new).unwrap();
+
+        let read = |path: &str| fs::read_to_string(new.join(path)).unwrap();
+        assert_eq!(read("main.js"), "new");
+        assert_eq!(read("js/app.js"), "new");
+        assert_eq!(read("data/app.db"), "db");
+        assert_eq!(read("js/cache/1.js"), "cached");
+        fs::remove_dir_all(dir).unwrap();
     }
-    exe.truncate(index);
-    return Ok(true);
 }
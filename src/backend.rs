@@ -1,6 +1,7 @@
 #[cfg(windows)]
 use std::os::windows::process::CommandExt;
 
+use std::collections::BTreeMap;
 use std::io::{BufReader, BufWriter};
 use std::path::PathBuf;
 use std::process::{Child, ChildStderr, ChildStdin, ChildStdout, Command, Stdio};
@@ -14,11 +15,12 @@ pub type ChildOut = BufReader<ChildStdout>;
 pub type ChildErr = BufReader<ChildStderr>;
 
 impl Backend {
-    pub fn new(command: Vec<String>, cwd: &PathBuf) -> Backend {
+    pub fn new(command: Vec<String>, cwd: &PathBuf, env: &BTreeMap<String, String>) -> Backend {
         #[cfg(windows)]
         let process = Command::new(&command[0])
             .args(&command[1..])
             .current_dir(cwd)
+            .envs(env)
             .creation_flags(0x08000000)
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
@@ -29,6 +31,7 @@ impl Backend {
         let process = Command::new(&command[0])
             .args(&command[1..])
             .current_dir(cwd)
+            .envs(env)
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
@@ -1,109 +1,129 @@
-use std::{env::current_dir, fs, path::PathBuf};
+use std::{
+    collections::BTreeMap,
+    env::current_dir,
+    fs,
+    path::{Component, Path, PathBuf},
+};
 
-use editpe::Image;
-#[cfg(windows)]
-use libsui::PortableExecutable;
-use tar::Builder;
+use editpe::{
+    constants::{CODE_PAGE_ID_EN_US, LANGUAGE_ID_EN_US},
+    types::{VersionU16, VersionU32},
+    Image, VersionInfo, VersionStringTable,
+};
 
 use crate::{
-    get_target,
-    shared::{embed_error::EmbedError, embed_trailer::TRAILER},
+    pack::{hash, Pack},
+    package::read_image,
+    shared::{
+        embed_error::EmbedError,
+        embed_trailer::TRAILER,
+        payload::{Manifest, PackInfo, FORMAT_VERSION, RUNTIME_MARKER_PREFIX},
+    },
+    target::{executable_targets, Arch, Os, Target},
     Config,
 };
 
+static DEFAULT_ICON: &str = "favicon.ico";
+static ICO_HEADER: [u8; 4] = [0, 0, 1, 0];
+static APP_ID_HASH_LEN: usize = 8;
+
 pub struct Base(Vec<u8>);
 
 impl Base {
-    pub fn load(target: &str) -> Result<Base, EmbedError> {
-        println!("{target}");
-        let exe = if target == "windows" {
-            include_bytes!("../runtimes/runtime-windows.exe").to_vec()
-        } else if target == "linux" {
-            include_bytes!("../runtimes/runtime-linux").to_vec()
-        } else if target == "macos" {
-            include_bytes!("../runtimes/runtime-macos").to_vec()
-        } else {
-            return Err(EmbedError(format!("Unknown target: {target}")));
+    pub fn load(target: Target) -> Result<Base, EmbedError> {
+        let exe: &[u8] = match (target.os, target.arch) {
+            (Os::Windows, Arch::X86_64) => {
+                include_bytes!("../runtimes/runtime-windows-x86_64.exe")
+            }
+            (Os::Windows, Arch::Aarch64) => {
+                include_bytes!("../runtimes/runtime-windows-aarch64.exe")
+            }
+            (Os::Linux, Arch::X86_64) => include_bytes!("../runtimes/runtime-linux-x86_64"),
+            (Os::Linux, Arch::Aarch64) => include_bytes!("../runtimes/runtime-linux-aarch64"),
+            (Os::Macos, Arch::X86_64) => include_bytes!("../runtimes/runtime-macos-x86_64"),
+            (Os::Macos, Arch::Aarch64) => include_bytes!("../runtimes/runtime-macos-aarch64"),
         };
+        Ok(Base(exe.to_vec()))
+    }
+
+    /**
+     * Uses a runtime from disk, checking it was built for the target and the payload format of this compiler
+     */
+    pub fn from_file(path: &Path, target: Target) -> Result<Base, EmbedError> {
+        let exe = fs::read(path).map_err(|_| EmbedError(format!("Unable to read {path:?}")))?;
+        if exe.ends_with(TRAILER) {
+            return Err(EmbedError(format!(
+                "{path:?} is a compiled application, not a runtime"
+            )));
+        }
+        match runtime_version(&exe) {
+            Some(FORMAT_VERSION) => {}
+            Some(version) => return Err(EmbedError(format!(
+                "{path:?} is a runtime for payload format {version}, this deutron writes format {FORMAT_VERSION}"
+            ))),
+            None => return Err(EmbedError(format!("{path:?} is not a deutron runtime"))),
+        }
+        let targets = executable_targets(&exe);
+        if !targets.contains(&target) {
+            let found: Vec<String> = targets.iter().map(Target::to_string).collect();
+            let found = if found.is_empty() {
+                "an unsupported platform".to_string()
+            } else {
+                found.join(", ")
+            };
+            return Err(EmbedError(format!(
+                "{path:?} is a runtime for {found}, not for {target}, pass --target to match it"
+            )));
+        }
         Ok(Base(exe))
     }
 
     /**
      * Write Embed Data
      */
-    #[cfg(windows)]
-    pub fn set_icon(&mut self, icon_path: &str) -> Result<(), EmbedError> {
-        let pe = PortableExecutable::from(&self.0)
-            .map_err(|_| EmbedError("Unable to read executable".into()))?;
+    pub fn set_icon(&mut self, icon_path: &Path) -> Result<(), EmbedError> {
+        let mut image =
+            Image::parse(&self.0).map_err(|_| EmbedError("Unable to read executable".into()))?;
+        let mut resources = image.resource_directory().cloned().unwrap_or_default();
 
         let icon = fs::read(icon_path).map_err(|_| EmbedError("Unable to read icon".into()))?;
-        let mut result: Vec<u8> = vec![];
-        pe.set_icon(icon)
-            .map_err(|_| EmbedError("Unable to set icon".into()))?
-            .build(&mut result)
+        // ICO files are used as is, other images are resized to the usual resolutions
+        let result = if icon.starts_with(&ICO_HEADER) {
+            resources.set_main_icon(icon)
+        } else {
+            resources.set_main_icon(read_image(&icon)?)
+        };
+        result.map_err(|_| EmbedError("Unable to set icon".into()))?;
+        image
+            .set_resource_directory(resources)
             .map_err(|_| EmbedError("Unable to build after setting icon".into()))?;
-        self.0 = result;
+        self.0 = image.data().to_vec();
         println!("Set icon to {:?}", icon_path);
         Ok(())
     }
-    #[cfg(not(windows))]
-    pub fn set_icon(&mut self, _: &str) -> Result<(), EmbedError> {
-        Err(EmbedError(
-            "Not supported for targets other then windows".to_string(),
-        ))
-    }
-
-    pub fn add_pack(&mut self, path: &PathBuf) -> Result<(), EmbedError> {
-        let data = Base::pack(path)?;
-        self.add_data(&data);
-        Ok(())
-    }
 
-    pub fn add_string(&mut self, text: &str) {
-        self.add_data(text.as_bytes());
-    }
-
-    pub fn add_strings(&mut self, mut list: Vec<String>) {
-        let len = list.len();
-        while let Some(entry) = list.pop() {
-            self.add_string(&entry);
+    pub fn add_pack(&mut self, pack: &Pack) -> PackInfo {
+        let offset = self.0.len() as u64;
+        self.0.extend_from_slice(&pack.data);
+        PackInfo {
+            offset,
+            ..pack.info.clone()
         }
-        self.add_sized(&len);
-    }
-
-    pub fn add_data(&mut self, data: &[u8]) {
-        self.0.extend_from_slice(data);
-        self.add_sized(&data.len());
     }
 
-    pub fn add_sized<T: Sized>(&mut self, data: &T) {
-        unsafe {
-            let raw_data = any_as_u8_slice(data);
-            self.0.extend_from_slice(raw_data);
-        };
+    pub fn add_manifest(&mut self, manifest: &Manifest) -> Result<(), EmbedError> {
+        let data = serde_json::to_vec(manifest)
+            .map_err(|_| EmbedError("Unable to serialize manifest".into()))?;
+        self.0.extend_from_slice(&data);
+        self.0.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        self.0.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        Ok(())
     }
 
     pub fn add_trailer(&mut self) {
         self.0.extend_from_slice(TRAILER);
     }
 
-    fn pack(path: &PathBuf) -> Result<Vec<u8>, EmbedError> {
-        let md = fs::metadata(path)
-            .map_err(|_| EmbedError("Failed to get meta data for pack file".into()))?;
-        let mut ar = Builder::new(Vec::new());
-
-        if md.is_dir() {
-            ar.append_dir_all(".", path)
-                .map_err(|_| EmbedError("Failed to add dir to pack".into()))?;
-        } else {
-            ar.append_path(path)
-                .map_err(|_| EmbedError("Failed to add file to pack".into()))?;
-        }
-
-        ar.into_inner()
-            .map_err(|_| EmbedError("Failed to read magic 64 number".into()))
-    }
-
     pub fn disable_terminal(&mut self) -> Result<(), EmbedError> {
         let mut image =
             Image::parse(&self.0).map_err(|_| EmbedError("Unable to read executable".into()))?;
@@ -112,31 +132,358 @@ impl Base {
         Ok(())
     }
 
+    /**
+     * Writes the VERSIONINFO resource shown in the file properties on windows
+     */
+    pub fn set_version_info(&mut self, config: &Config, file_name: &str) -> Result<(), EmbedError> {
+        let mut image =
+            Image::parse(&self.0).map_err(|_| EmbedError("Unable to read executable".into()))?;
+        let mut resources = image.resource_directory().cloned().unwrap_or_default();
+
+        let file_name = file_name.to_string();
+        let product_name = match &config.product_name {
+            Some(name) => name.clone(),
+            None => get_app_name(config)?,
+        };
+        let version = &config.set_version;
+        let fields = [
+            ("CompanyName", config.company.as_ref()),
+            (
+                "FileDescription",
+                config.description.as_ref().or(Some(&product_name)),
+            ),
+            ("FileVersion", Some(version)),
+            ("InternalName", Some(&file_name)),
+            ("LegalCopyright", config.copyright.as_ref()),
+            ("OriginalFilename", Some(&file_name)),
+            ("ProductName", Some(&product_name)),
+            ("ProductVersion", Some(version)),
+        ];
+        let mut table = VersionStringTable {
+            key: format!("{LANGUAGE_ID_EN_US:04X}{CODE_PAGE_ID_EN_US:04X}"),
+            strings: Default::default(),
+        };
+        for (key, value) in fields {
+            if let Some(value) = value {
+                table.strings.insert(key.to_string(), value.clone());
+            }
+        }
+
+        let mut info = VersionInfo::default();
+        info.info.file_version = parse_version(version);
+        info.info.product_version = parse_version(version);
+        info.strings.push(table);
+        info.vars.push(VersionU16 {
+            major: LANGUAGE_ID_EN_US,
+            minor: CODE_PAGE_ID_EN_US,
+        });
+        resources
+            .set_version_info(&info)
+            .map_err(|_| EmbedError("Unable to set version info".into()))?;
+        image
+            .set_resource_directory(resources)
+            .map_err(|_| EmbedError("Unable to write version info".into()))?;
+        self.0 = image.data().to_vec();
+        Ok(())
+    }
+
     pub fn write(&mut self, target_path: PathBuf) -> Result<PathBuf, EmbedError> {
         self.add_trailer();
         fs::write(&target_path, self.0.clone())
             .map_err(|_| EmbedError("Unable to write executable".into()))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&target_path, fs::Permissions::from_mode(0o755))
+                .map_err(|_| EmbedError("Unable to make executable".into()))?;
+        }
         Ok(target_path)
     }
 }
 
-unsafe fn any_as_u8_slice<T: Sized>(p: &T) -> &[u8] {
-    ::core::slice::from_raw_parts((p as *const T) as *const u8, ::core::mem::size_of::<T>())
+fn runtime_version(exe: &[u8]) -> Option<u32> {
+    let prefix_len = RUNTIME_MARKER_PREFIX.len();
+    exe.windows(prefix_len)
+        .enumerate()
+        .filter(|(_, window)| *window == RUNTIME_MARKER_PREFIX)
+        .find_map(|(index, _)| {
+            let rest = &exe[index + prefix_len..];
+            let len = rest.iter().take_while(|byte| byte.is_ascii_digit()).count();
+            if rest.get(len) != Some(&b';') {
+                return None;
+            }
+            std::str::from_utf8(&rest[..len]).ok()?.parse().ok()
+        })
+}
+
+/**
+ * Windows versions are four numbers, "1.2.3-beta" becomes 1.2.3.0
+ */
+fn parse_version(version: &str) -> VersionU32 {
+    let mut parts = version.split('.').map(|part| {
+        let digits: String = part.chars().take_while(char::is_ascii_digit).collect();
+        digits.parse::<u16>().unwrap_or(0) as u32
+    });
+    let mut next = || parts.next().unwrap_or(0);
+    VersionU32 {
+        major: next() << 16 | next(),
+        minor: next() << 16 | next(),
+    }
 }
 
-pub fn get_out_path(config: &Config) -> Result<PathBuf, EmbedError> {
+/**
+ * The output path when compiling for a single target, without extension
+ */
+fn get_out_base(config: &Config) -> Result<PathBuf, EmbedError> {
     let cwd = current_dir().map_err(|_| EmbedError("Unable to find CWD".into()))?;
-    if let Some(name) = &config.out {
-        Ok(cwd.join(name))
-    } else {
-        let target = get_target(config);
-        if target == "windows" {
-            Ok(cwd.join(format!(
-                "{}.exe",
-                cwd.file_name().unwrap().to_str().unwrap()
-            )))
-        } else {
-            Ok(cwd.join(cwd.file_name().unwrap().to_str().unwrap()))
+    match &config.out {
+        Some(name) => Ok(cwd.join(name)),
+        None => Ok(cwd.join(cwd.file_name().unwrap())),
+    }
+}
+
+fn strip_extension(name: &str) -> &str {
+    name.strip_suffix(".exe")
+        .or(name.strip_suffix(".app"))
+        .unwrap_or(name)
+}
+
+/**
+ * With more than one target the target is added to the name, like app-linux-x86_64
+ */
+pub fn get_out_path(config: &Config, target: Target, suffix: bool) -> Result<PathBuf, EmbedError> {
+    let mut path = get_out_base(config)?;
+    let name = path.file_name().unwrap().to_string_lossy().to_string();
+    if suffix {
+        path.set_file_name(format!("{}-{target}", strip_extension(&name)));
+    }
+    let extension = match target.os {
+        Os::Windows if config.out.is_none() || suffix => ".exe",
+        // macOS only launches a bundle when it has the .app extension
+        Os::Macos => ".app",
+        _ => "",
+    };
+    if path.to_string_lossy().ends_with(extension) {
+        return Ok(path);
+    }
+    let mut path = path.into_os_string();
+    path.push(extension);
+    Ok(path.into())
+}
+
+/**
+ * The --icon file, or favicon.ico in the include directory when it exists
+ */
+pub fn get_icon(config: &Config) -> Result<Option<PathBuf>, EmbedError> {
+    match &config.icon {
+        Some(icon) if icon.is_file() => Ok(Some(icon.clone())),
+        Some(icon) => Err(EmbedError(format!("Icon {icon:?} not found"))),
+        None => Ok(Some(config.include.join(DEFAULT_ICON)).filter(|icon| icon.is_file())),
+    }
+}
+
+pub fn get_app_id(config: &Config) -> Result<String, EmbedError> {
+    match &config.app_id {
+        Some(id) if !id.is_empty() && id.chars().all(is_valid_id) => Ok(id.clone()),
+        Some(id) => Err(EmbedError(format!(
+            "Invalid app id \"{id}\", only letters, digits, '.', '_' and '-' are allowed"
+        ))),
+        None => {
+            // Apps sharing a file name are told apart by the project they're built from
+            let include = config
+                .include
+                .canonicalize()
+                .unwrap_or(config.include.clone());
+            let project = hash(include.to_string_lossy().as_bytes());
+            Ok(format!(
+                "{}-{}",
+                get_app_name(config)?,
+                &project[..APP_ID_HASH_LEN]
+            ))
+        }
+    }
+}
+
+/**
+ * The app id when given, the output file name otherwise
+ */
+fn get_app_name(config: &Config) -> Result<String, EmbedError> {
+    if config.app_id.is_some() {
+        return get_app_id(config);
+    }
+    Ok(
+        strip_extension(&get_out_base(config)?.file_name().unwrap().to_string_lossy())
+            .chars()
+            .map(|c| if is_valid_id(c) { c } else { '-' })
+            .collect(),
+    )
+}
+
+fn is_valid_id(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "._-".contains(c)
+}
+
+/**
+ * The --env variables, a variable given twice keeps the last value
+ */
+pub fn get_env(config: &Config) -> Result<BTreeMap<String, String>, EmbedError> {
+    let mut env = BTreeMap::new();
+    for variable in &config.env {
+        match variable.split_once('=') {
+            Some((key, value)) if !key.is_empty() => {
+                env.insert(key.to_string(), value.to_string());
+            }
+            _ => {
+                return Err(EmbedError(format!(
+                    "Invalid environment variable \"{variable}\", expected KEY=VALUE"
+                )))
+            }
+        }
+    }
+    Ok(env)
+}
+
+/**
+ * The --cwd folder, which has to be inside the include directory as it's unpacked with it
+ */
+pub fn get_cwd(config: &Config) -> Result<Option<String>, EmbedError> {
+    let Some(cwd) = &config.cwd else {
+        return Ok(None);
+    };
+    let mut parts = vec![];
+    for component in cwd.components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_string_lossy()),
+            Component::CurDir => {}
+            _ => {
+                return Err(EmbedError(format!(
+                    "Working directory {cwd:?} must be a folder inside the include directory"
+                )))
+            }
+        }
+    }
+    if !config.include.join(cwd).is_dir() {
+        return Err(EmbedError(format!(
+            "Working directory {cwd:?} not found in the include directory"
+        )));
+    }
+    Ok(Some(parts.join("/")).filter(|cwd| !cwd.is_empty()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::PathBuf};
+
+    use clap::Parser;
+
+    use super::{get_app_id, get_cwd, get_env, get_out_path, parse_version};
+    use crate::{target::Target, Config};
+
+    fn config(args: &[&str]) -> Config {
+        Config::parse_from(["deutron"].iter().chain(args).chain(&["node"]))
+    }
+
+    #[test]
+    fn versions() {
+        let version = |version| {
+            let version = parse_version(version);
+            (version.major, version.minor)
+        };
+        assert_eq!(version("1.2.3.4"), (1 << 16 | 2, 3 << 16 | 4));
+        assert_eq!(version("1.0"), (1 << 16, 0));
+        assert_eq!(version("2"), (2 << 16, 0));
+        assert_eq!(version("1.2.3-beta"), (1 << 16 | 2, 3 << 16));
+        assert_eq!(version("v1"), (0, 0));
+        assert_eq!(version("70000.1"), (1, 0));
+    }
+
+    #[test]
+    fn env_variables() {
+        let env = get_env(&config(&["--env", "A=1", "--env", "B=x=y", "--env", "C="])).unwrap();
+        assert_eq!(env.get("A").map(String::as_str), Some("1"));
+        assert_eq!(env.get("B").map(String::as_str), Some("x=y"));
+        assert_eq!(env.get("C").map(String::as_str), Some(""));
+        assert!(get_env(&config(&[])).unwrap().is_empty());
+
+        for variable in ["A", "=1"] {
+            assert!(
+                get_env(&config(&["--env", variable])).is_err(),
+                "{variable}"
+            );
         }
     }
+
+    #[test]
+    fn working_directory() {
+        let include = env::temp_dir().join(format!("deutron-test-{}-cwd", std::process::id()));
+        fs::create_dir_all(include.join("server/src")).unwrap();
+        let include = include.to_string_lossy().to_string();
+        let cwd = |cwd: &str| get_cwd(&config(&["--include", &include, "--cwd", cwd]));
+
+        assert_eq!(cwd("server/src").unwrap().as_deref(), Some("server/src"));
+        assert_eq!(cwd("./server/").unwrap().as_deref(), Some("server"));
+        assert_eq!(cwd(".").unwrap(), None);
+        assert!(cwd("missing").is_err());
+        assert!(cwd("../server").is_err());
+        assert!(cwd(&include).is_err());
+        assert_eq!(get_cwd(&config(&["--include", &include])).unwrap(), None);
+
+        fs::remove_dir_all(PathBuf::from(include)).unwrap();
+    }
+
+    #[test]
+    fn app_ids() {
+        let id =
+            |include: &str| get_app_id(&config(&["--out", "my app.exe", "--include", include]));
+        let first = id("./src").unwrap();
+        assert!(first.starts_with("my-app-"), "{first}");
+        assert_eq!(first, id("./src/").unwrap());
+        assert_ne!(first, id("./docs").unwrap());
+
+        let given = config(&["--out", "my app", "--app-id", "com.example.app"]);
+        assert_eq!(get_app_id(&given).unwrap(), "com.example.app");
+        assert!(get_app_id(&config(&["--app-id", "no/slash"])).is_err());
+    }
+
+    #[test]
+    fn out_paths() {
+        let cwd = env::current_dir().unwrap();
+        let out = |args: &[&str], target: &str, suffix: bool| {
+            let path = get_out_path(&config(args), Target::parse(target).unwrap(), suffix);
+            path.unwrap().strip_prefix(&cwd).unwrap().to_path_buf()
+        };
+        let app = ["--out", "app"];
+        assert_eq!(out(&app, "linux", false), PathBuf::from("app"));
+        assert_eq!(out(&app, "macos", false), PathBuf::from("app.app"));
+        // An explicit name is used as is on windows
+        assert_eq!(out(&app, "windows", false), PathBuf::from("app"));
+        assert_eq!(
+            out(&["--out", "app.exe"], "windows", false),
+            PathBuf::from("app.exe")
+        );
+
+        // Several targets at once get the target in their name
+        assert_eq!(
+            out(&app, "linux-aarch64", true),
+            PathBuf::from("app-linux-aarch64")
+        );
+        assert_eq!(
+            out(&app, "windows", true),
+            PathBuf::from("app-windows-x86_64.exe")
+        );
+        assert_eq!(
+            out(&["--out", "app.exe"], "windows", true),
+            PathBuf::from("app-windows-x86_64.exe")
+        );
+        assert_eq!(
+            out(&["--out", "dist/app"], "macos-arm64", true),
+            PathBuf::from("dist/app-macos-aarch64.app")
+        );
+
+        let name = cwd.file_name().unwrap().to_string_lossy().to_string();
+        assert_eq!(
+            out(&[], "windows", false),
+            PathBuf::from(format!("{name}.exe"))
+        );
+    }
 }
@@ -1,7 +1,10 @@
-use crate::state::STATE;
-use std::panic;
+use crate::state::{STARTED, STATE};
+use std::{panic, sync::atomic::Ordering};
 
 pub fn cleanup_backend() {
+    if !STARTED.load(Ordering::SeqCst) {
+        return;
+    }
     STATE.backend.lock().unwrap().process.kill().ok().unwrap();
 }
 
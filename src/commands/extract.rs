@@ -0,0 +1,32 @@
+use std::{fs, path::Path};
+
+use crate::{app::App, shared::embed_error::EmbedError};
+
+/**
+ * Writes the packed files and the embedded manifest to a directory, without running the app
+ */
+pub fn extract(file: &Path, out: &Path) -> Result<(), EmbedError> {
+    let is_empty = fs::read_dir(out).map_or(true, |mut entries| entries.next().is_none());
+    if !is_empty {
+        return Err(EmbedError(format!("Output directory {out:?} is not empty")));
+    }
+    let mut app = App::open(file)?;
+    let manifest = app.manifest().clone();
+    let text = serde_json::to_string_pretty(&manifest)
+        .map_err(|_| EmbedError("Unable to serialize manifest".into()))?;
+
+    App::unpack(&out.join("pack"), app.take_reader(&manifest.pack)?)?;
+    if let Some(frontend) = &manifest.frontend {
+        App::unpack(&out.join("frontend"), app.take_reader(frontend)?)?;
+    }
+    if let Some(interpreter) = &manifest.interpreter {
+        App::unpack(
+            &out.join("interpreter"),
+            app.take_reader(&interpreter.pack)?,
+        )?;
+    }
+    fs::write(out.join("manifest.json"), text)
+        .map_err(|_| EmbedError("Unable to write manifest".into()))?;
+    println!("Extracted to: {out:?}");
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use std::path::Path;
+
+use crate::{
+    app::App,
+    shared::{
+        embed_error::EmbedError,
+        payload::{Compression, PackInfo},
+    },
+};
+
+pub fn inspect(file: &Path, json: bool) -> Result<(), EmbedError> {
+    let app = App::open(file)?;
+    let manifest = app.manifest();
+    if json {
+        let text = serde_json::to_string_pretty(manifest)
+            .map_err(|_| EmbedError("Unable to serialize manifest".into()))?;
+        println!("{text}");
+        return Ok(());
+    }
+
+    let flags = &manifest.flags;
+    println!("App id:    {}", manifest.id);
+    println!("Command:   {}", manifest.command.join(" "));
+    println!("Version:   {}", manifest.version);
+    println!("Verbose:   {}", flags.verbose);
+    println!("No cache:  {}", flags.no_cache);
+    println!("Dev tools: {}", flags.dev_tools);
+    println!("Extract:   {:?}", manifest.extract_dir);
+    if let Some(cwd) = &manifest.cwd {
+        println!("Cwd:       {cwd}");
+    }
+    for (key, value) in &manifest.env {
+        println!("Env:       {key}={value}");
+    }
+    match &manifest.frontend {
+        Some(frontend) => {
+            print_pack("Backend", &manifest.pack);
+            print_pack("Frontend", frontend);
+        }
+        None => print_pack("Pack", &manifest.pack),
+    }
+    if let Some(interpreter) = &manifest.interpreter {
+        print_pack(
+            &format!("Interpreter {}", interpreter.exe),
+            &interpreter.pack,
+        );
+    }
+    Ok(())
+}
+
+fn print_pack(name: &str, pack: &PackInfo) {
+    let compression = match pack.compression {
+        Compression::None => "uncompressed".to_string(),
+        Compression::Zstd(level) => format!("zstd level {level}"),
+    };
+    let total: u64 = pack.files.iter().map(|file| file.size).sum();
+    println!(
+        "{name}: {} files, {} ({}, {} stored)",
+        pack.files.len(),
+        format_size(total),
+        compression,
+        format_size(pack.len)
+    );
+    println!("  Hash: {}", pack.hash);
+    for file in &pack.files {
+        println!("  {:>10}  {}", format_size(file.size), file.path);
+    }
+}
+
+fn format_size(bytes: u64) -> String {
+    let units = ["B", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", units[unit])
+    }
+}
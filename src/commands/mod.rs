@@ -0,0 +1,37 @@
+use std::path::PathBuf;
+
+use clap::Subcommand;
+
+use crate::shared::embed_error::EmbedError;
+
+pub mod extract;
+pub mod inspect;
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Action {
+    #[command(about = "Prints what a compiled executable contains")]
+    Inspect {
+        #[clap(help = "The compiled executable")]
+        file: PathBuf,
+        #[clap(long, help = "Prints the embedded manifest as JSON")]
+        json: bool,
+    },
+    #[command(
+        about = "Writes the packed files and manifest of a compiled executable to a directory"
+    )]
+    Extract {
+        #[clap(help = "The compiled executable")]
+        file: PathBuf,
+        #[clap(long, short, help = "The directory to extract to")]
+        out: PathBuf,
+    },
+}
+
+impl Action {
+    pub fn run(self) -> Result<(), EmbedError> {
+        match self {
+            Action::Inspect { file, json } => inspect::inspect(&file, json),
+            Action::Extract { file, out } => extract::extract(&file, &out),
+        }
+    }
+}
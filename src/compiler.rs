@@ -1,23 +1,38 @@
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 use std::{fs::remove_file, path::PathBuf};
 
 use crate::backend::Backend;
-use crate::base::{get_out_path, Base};
+use crate::base::{get_app_id, get_cwd, get_env, get_icon, get_out_path, Base};
 use crate::cleanup::cleanup_onexit;
+use crate::commands::Action;
+use crate::pack::Packs;
+use crate::package::{linux::write_appdir, macos::write_app};
+use crate::project::load_config;
 use crate::shared::embed_error::{EmbedError, UnwrapPrintln};
+use crate::shared::payload::{ExtractDir, Flags, Interpreter, Manifest};
 use crate::state::State;
-use crate::temp::get_temp_dir;
+use crate::target::{Os, Target};
+use crate::temp::{get_app_dir, get_webview_dir};
+use crate::watch::watch;
+use crate::webview::vfs::DiskFs;
 use clap::{command, Parser};
 use serde::{Deserialize, Serialize};
 
 #[macro_use]
 mod macros;
+mod app;
 mod backend;
 mod base;
 mod cleanup;
+mod commands;
+mod pack;
+mod package;
+mod project;
 mod shared;
 mod state;
+mod target;
 mod temp;
+mod watch;
 mod webview;
 
 pub enum WebviewIcon<'a> {
@@ -27,8 +42,18 @@ pub enum WebviewIcon<'a> {
 }
 
 #[derive(Parser, Debug, Serialize, Deserialize, Clone)]
-#[command(author, version, about, long_about = None)]
+#[command(
+    author,
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    disable_help_subcommand = true
+)]
 pub struct Config {
+    #[command(subcommand)]
+    #[serde(skip)]
+    pub action: Option<Action>,
     #[clap(
         long,
         short,
@@ -53,10 +78,64 @@ pub struct Config {
     #[clap(
         long,
         short,
+        visible_alias = "backend",
         default_value = "./",
-        help = "The directory that is packed into the binary"
+        help = "The directory that is packed into the binary, the backend runs from it"
     )]
     pub include: PathBuf,
+    #[clap(
+        long,
+        help = "The directory served to the webviews, packed separately from the backend [default: include directory]"
+    )]
+    pub frontend: Option<PathBuf>,
+    #[clap(long, help = "Only pack files matching this glob, can be repeated")]
+    pub include_glob: Vec<String>,
+    #[clap(long, help = "Don't pack files matching this glob, can be repeated")]
+    pub exclude: Vec<String>,
+    #[clap(
+        long,
+        default_value = "cache",
+        help = "Where the app unpacks its files: cache, data or temp for those user directories, or a path relative to the executable"
+    )]
+    pub extract_dir: String,
+    #[clap(
+        long,
+        value_name = "KEY=VALUE",
+        help = "Environment variable set for the backend, can be repeated"
+    )]
+    pub env: Vec<String>,
+    #[clap(
+        long,
+        help = "Working directory of the backend, a folder inside the include directory [default: include directory]"
+    )]
+    pub cwd: Option<PathBuf>,
+    #[clap(
+        long,
+        help = "Restarts the backend in --debug mode when a file matching this glob changes, can be repeated"
+    )]
+    pub restart_on: Vec<String>,
+    #[clap(
+        long,
+        help = "Loads the windows from a frontend dev server in --debug mode, like http://localhost:5173"
+    )]
+    pub frontend_url: Option<String>,
+    #[clap(
+        long,
+        default_value = "19",
+        value_parser = clap::value_parser!(i32).range(0..=22),
+        help = "Zstd compression level of the packed directory, 0 disables compression"
+    )]
+    pub compression: i32,
+    #[clap(
+        long,
+        help = "Packs files in a fixed order with normalized metadata, so the same files give the same binary"
+    )]
+    pub reproducible: bool,
+    #[clap(
+        long,
+        help = "Icon of the application, png or ico [default: favicon.ico in the include directory]"
+    )]
+    pub icon: Option<PathBuf>,
     #[clap(long, short, help = "Forces deutron to unpack on every boot")]
     pub no_cache: bool,
     #[clap(
@@ -66,7 +145,50 @@ pub struct Config {
     pub dev_tools: bool,
     #[clap(long, short, help = "Logs all info and messages send")]
     pub verbose: bool,
-    #[clap(long, help = "Compile for a given target: windows, linux or macos")]
+    #[clap(
+        long,
+        help = "Product name shown in the file properties on windows [default: app id or output file name]"
+    )]
+    pub product_name: Option<String>,
+    #[clap(
+        long,
+        help = "Description shown in the file properties and task manager on windows [default: product name]"
+    )]
+    pub description: Option<String>,
+    #[clap(long, help = "Company shown in the file properties on windows")]
+    pub company: Option<String>,
+    #[clap(long, help = "Copyright shown in the file properties on windows")]
+    pub copyright: Option<String>,
+    #[clap(
+        long,
+        help = "Identifier of the application, used to name its extraction directories [default: output file name and a hash of the include path]"
+    )]
+    pub app_id: Option<String>,
+    #[clap(
+        long,
+        help = "Writes an AppDir with a .desktop entry and icons instead of a single binary, linux only"
+    )]
+    pub appdir: bool,
+    #[clap(
+        long,
+        help = "Embeds the executable of the command, so it doesn't have to be installed"
+    )]
+    pub bundle_interpreter: bool,
+    #[clap(
+        long,
+        requires = "bundle_interpreter",
+        help = "File or directory bundled next to the interpreter, can be repeated"
+    )]
+    pub bundle_file: Vec<PathBuf>,
+    #[clap(
+        long,
+        help = "Runtime executable to use instead of the one built into deutron"
+    )]
+    pub runtime: Option<PathBuf>,
+    #[clap(
+        long,
+        help = "Compile for a given target: windows, linux or macos, optionally with an arch like linux-aarch64, or a target triple. Use a comma separated list or \"all\" to compile for several targets at once"
+    )]
     pub target: Option<String>,
     #[arg(
         trailing_var_arg = true,
@@ -77,63 +199,198 @@ pub struct Config {
 }
 
 fn main() {
-    let config = Config::parse();
-    if config.debug {
+    let config = load_config().unwrap_or_println();
+    if let Some(action) = config.action {
+        action.run().unwrap_or_println();
+    } else if config.debug {
         cleanup_onexit();
         let manager = webview::webview_manager::WebViewManager::new();
-        manager.start(Some(vec![]));
+        manager.start(Some(vec![]), |proxy| {
+            watch(&config, proxy).unwrap_or_println()
+        });
     } else {
         embed(config).unwrap_or_println();
     }
 }
 
-pub fn get_target(config: &Config) -> &str {
-    match config.target.as_ref() {
-        Some(target) => target,
-        None => std::env::consts::OS,
+/**
+ * The targets to compile for: the host by default, "all" or a comma separated list
+ */
+pub fn get_targets(config: &Config) -> Result<Vec<Target>, EmbedError> {
+    let mut targets = vec![];
+    match config.target.as_deref() {
+        None => targets.push(Target::host()?),
+        Some("all") => targets = Target::all(),
+        Some(list) => {
+            for name in list
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+            {
+                let target = Target::parse(name)?;
+                if !targets.contains(&target) {
+                    targets.push(target);
+                }
+            }
+        }
+    }
+    if targets.is_empty() {
+        return Err(EmbedError("No target given".into()));
+    }
+    Ok(targets)
+}
+
+/**
+ * The --frontend-url dev server, ending with a slash so window urls can be appended
+ */
+fn get_frontend_url(config: &Config) -> Result<Option<String>, EmbedError> {
+    let Some(url) = &config.frontend_url else {
+        return Ok(None);
+    };
+    let uri: http::Uri = url
+        .parse()
+        .map_err(|_| EmbedError(format!("Invalid frontend url {url}")))?;
+    if !matches!(uri.scheme_str(), Some("http" | "https")) || uri.authority().is_none() {
+        return Err(EmbedError(format!(
+            "Frontend url {url} must start with http:// or https://"
+        )));
     }
+    Ok(Some(format!("{}/", url.trim_end_matches('/'))))
 }
 
 fn embed(config: Config) -> Result<(), EmbedError> {
-    let target = get_target(&config);
-    let mut base = Base::load(target).unwrap_or_println();
+    if config.frontend_url.is_some() {
+        return Err(EmbedError(
+            "--frontend-url only works with --debug, build the frontend before compiling".into(),
+        ));
+    }
+    let targets = get_targets(&config)?;
+    if config.appdir && targets.iter().any(|target| target.os != Os::Linux) {
+        return Err(EmbedError("--appdir is only supported for linux".into()));
+    }
+    if config.runtime.is_some() && targets.len() > 1 {
+        return Err(EmbedError(
+            "--runtime can only be used when compiling for a single target".into(),
+        ));
+    }
+    if config.bundle_interpreter {
+        let host = Target::host()?;
+        if let Some(target) = targets.iter().find(|target| **target != host) {
+            return Err(EmbedError(format!(
+                "Can't bundle the interpreter when compiling for {target} on {host}"
+            )));
+        }
+    }
+
+    let packs = Packs::new(&config)?;
+    for target in &targets {
+        embed_target(&config, *target, &packs, targets.len() > 1)?;
+    }
+    Ok(())
+}
+
+fn embed_target(
+    config: &Config,
+    target: Target,
+    packs: &Packs,
+    suffix: bool,
+) -> Result<(), EmbedError> {
+    let mut base = match &config.runtime {
+        Some(runtime) => Base::from_file(runtime, target)?,
+        None => Base::load(target)?,
+    };
 
-    if !config.terminal && target == "windows" {
-        base.disable_terminal()?;
+    let target_path = get_out_path(config, target, suffix)?;
+    let icon = get_icon(config)?;
+    if target.os == Os::Windows {
+        if !config.terminal {
+            base.disable_terminal()?;
+        }
+        if let Some(icon) = &icon {
+            base.set_icon(icon)?;
+        }
+        let file_name = target_path.file_name().unwrap().to_string_lossy();
+        base.set_version_info(config, &file_name)?;
     }
 
-    let target_path = get_out_path(&config)?;
-    if target_path.exists() {
+    if target_path.is_file() {
         remove_file(&target_path).map_err(|_| {
             EmbedError(format!("Can't write binary, make sure the application is closed").into())
         })?;
     }
-    let icon = "favicon.ico";
-    if target == "windows" {
-        base.set_icon(icon).ok();
-    }
-    base.add_pack(&config.include)?;
-    base.add_sized::<bool>(&config.verbose);
-    base.add_sized::<bool>(&config.no_cache);
-    base.add_sized::<bool>(&config.dev_tools);
-    base.add_string(&config.set_version);
-    base.add_strings(config.command.clone());
-    println!("Compiled to: {:?}", base.write(target_path)?);
+    let pack = base.add_pack(&packs.backend);
+    let frontend = packs.frontend.as_ref().map(|pack| base.add_pack(pack));
+    let interpreter = packs.interpreter.as_ref().map(|(exe, pack)| Interpreter {
+        exe: exe.clone(),
+        pack: base.add_pack(pack),
+    });
+    let id = get_app_id(config)?;
+    base.add_manifest(&Manifest {
+        id: id.clone(),
+        command: config.command.clone(),
+        version: config.set_version.clone(),
+        flags: Flags {
+            verbose: config.verbose,
+            no_cache: config.no_cache,
+            dev_tools: config.dev_tools,
+        },
+        pack,
+        frontend,
+        interpreter,
+        extract_dir: ExtractDir::parse(&config.extract_dir),
+        env: get_env(config)?,
+        cwd: get_cwd(config)?,
+    })?;
+    let out = if target.os == Os::Macos {
+        write_app(
+            &mut base,
+            &target_path,
+            &id,
+            &config.set_version,
+            icon.as_deref(),
+        )?
+    } else if config.appdir {
+        let (version, terminal) = (&config.set_version, config.terminal);
+        write_appdir(
+            &mut base,
+            &target_path,
+            &id,
+            version,
+            terminal,
+            icon.as_deref(),
+        )?
+    } else {
+        base.write(target_path)?
+    };
+    println!("Compiled to: {out:?}");
     Ok(())
 }
 
 fn start() -> Result<State, EmbedError> {
-    let config = Config::parse();
-    let mut backend = Backend::new(config.command.clone(), &PathBuf::from(&config.include));
+    let config = load_config()?;
+    let backend_env = get_env(&config)?;
+    let backend_cwd = match get_cwd(&config)? {
+        Some(cwd) => config.include.join(cwd),
+        None => config.include.clone(),
+    };
+    let mut backend = Backend::new(config.command.clone(), &backend_cwd, &backend_env);
     Ok(State {
         verbose: config.verbose,
         dev_tools: config.dev_tools,
-        icon: config.include.join("./favicon.ico"),
+        icon: get_icon(&config)?.unwrap_or(config.include.join("./favicon.ico")),
+        files: Arc::new(DiskFs(
+            config.frontend.clone().unwrap_or(config.include.clone()),
+        )),
+        frontend_url: get_frontend_url(&config)?,
+        pack_locks: vec![],
+        webview_dir: get_webview_dir(&get_app_dir(&get_app_id(&config)?, &ExtractDir::Temp)?),
         backend_dir: config.include,
-        webview_dir: get_temp_dir(&config.set_version, "webview").unwrap_or_println(),
+        command: config.command,
+        backend_cwd,
+        backend_env,
         backend_in: Mutex::new(backend.get_stdin()),
-        backend_out: Mutex::new(backend.get_stdout()),
-        backend_err: Mutex::new(backend.get_stderr()),
+        backend_out: Mutex::new(Some(backend.get_stdout())),
+        backend_err: Mutex::new(Some(backend.get_stderr())),
         backend: Mutex::new(backend),
     })
 }
@@ -0,0 +1,391 @@
+use std::{
+    env,
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+};
+
+use ignore::{overrides::OverrideBuilder, WalkBuilder};
+use sha2::{Digest, Sha256};
+use tar::{Archive, Builder, EntryType, Header, HeaderMode};
+
+use crate::{
+    shared::{
+        embed_error::EmbedError,
+        payload::{Compression, PackEntry, PackInfo},
+    },
+    Config,
+};
+
+static IGNORE_FILE: &str = ".deutronignore";
+
+/**
+ * A compressed tar archive, built once and appended to the runtime of every target
+ */
+pub struct Pack {
+    pub data: Vec<u8>,
+    pub info: PackInfo,
+}
+
+/**
+ * Everything packed into a binary
+ */
+pub struct Packs {
+    pub backend: Pack,
+    pub frontend: Option<Pack>,
+    /**
+     * File name of the interpreter executable, and the pack holding it
+     */
+    pub interpreter: Option<(String, Pack)>,
+}
+
+impl Packs {
+    pub fn new(config: &Config) -> Result<Packs, EmbedError> {
+        let backend = Pack::new(&config.include, config)?;
+        let frontend = match &config.frontend {
+            Some(path) => Some(Pack::new(path, config)?),
+            None => None,
+        };
+        let interpreter = if config.bundle_interpreter {
+            Some(Pack::interpreter(config)?)
+        } else {
+            None
+        };
+        Ok(Packs {
+            backend,
+            frontend,
+            interpreter,
+        })
+    }
+}
+
+impl Pack {
+    pub fn new(path: &Path, config: &Config) -> Result<Pack, EmbedError> {
+        let md = fs::metadata(path)
+            .map_err(|_| EmbedError("Failed to get meta data for pack file".into()))?;
+        let mtime = get_mtime(config)?;
+        let mut ar = Builder::new(Vec::new());
+
+        if md.is_dir() {
+            for file in Pack::walk(path, config)? {
+                let name = file.strip_prefix(path).unwrap();
+                Pack::append(&mut ar, &file, name, mtime)
+                    .map_err(|_| EmbedError(format!("Failed to add {name:?} to pack")))?;
+            }
+        } else {
+            Pack::append(&mut ar, path, path, mtime)
+                .map_err(|_| EmbedError("Failed to add file to pack".into()))?;
+        }
+
+        let tar = ar
+            .into_inner()
+            .map_err(|_| EmbedError("Failed to read magic 64 number".into()))?;
+        Pack::from_tar(tar, config)
+    }
+
+    /**
+     * Packs the executable of the backend command and the bundled files next to each other
+     */
+    pub fn interpreter(config: &Config) -> Result<(String, Pack), EmbedError> {
+        let Some(command) = config.command.first() else {
+            return Err(EmbedError("No command to bundle the interpreter of".into()));
+        };
+        let exe = which::which(command)
+            .map_err(|_| EmbedError(format!("Unable to find {command} to bundle")))?;
+
+        let mtime = get_mtime(config)?;
+        let mut ar = Builder::new(Vec::new());
+        for path in std::iter::once(&exe).chain(&config.bundle_file) {
+            let name = Path::new(
+                path.file_name()
+                    .ok_or(EmbedError(format!("Can't bundle {path:?}")))?,
+            );
+            let files = if path.is_dir() {
+                Pack::list_files(WalkBuilder::new(path).standard_filters(false))?
+                    .into_iter()
+                    .map(|file| (name.join(file.strip_prefix(path).unwrap()), file))
+                    .collect()
+            } else {
+                vec![(name.to_path_buf(), path.clone())]
+            };
+            for (name, file) in files {
+                Pack::append(&mut ar, &file, &name, mtime)
+                    .map_err(|_| EmbedError(format!("Failed to bundle {file:?}")))?;
+            }
+        }
+        let tar = ar
+            .into_inner()
+            .map_err(|_| EmbedError("Failed to bundle the interpreter".into()))?;
+        println!("Bundled interpreter: {exe:?}");
+        let name = exe.file_name().unwrap().to_string_lossy().to_string();
+        Ok((name, Pack::from_tar(tar, config)?))
+    }
+
+    fn from_tar(tar: Vec<u8>, config: &Config) -> Result<Pack, EmbedError> {
+        let compression = get_compression(config)?;
+        let files = Pack::index(&tar)?;
+        let hash = hash(&tar);
+        let data = Pack::compress(tar, compression)?;
+        let info = PackInfo {
+            offset: 0,
+            len: data.len() as u64,
+            compression,
+            hash,
+            files,
+        };
+        Ok(Pack { data, info })
+    }
+
+    /**
+     * Lists the files to pack, honoring --include-glob, --exclude and .deutronignore files
+     */
+    fn walk(path: &Path, config: &Config) -> Result<Vec<PathBuf>, EmbedError> {
+        let mut overrides = OverrideBuilder::new(path);
+        let globs = config.include_glob.iter().cloned();
+        let excludes = config.exclude.iter().map(|glob| format!("!{glob}"));
+        for glob in globs.chain(excludes) {
+            overrides
+                .add(&glob)
+                .map_err(|e| EmbedError(format!("Invalid glob {glob}: {e}")))?;
+        }
+        let overrides = overrides
+            .build()
+            .map_err(|e| EmbedError(format!("Invalid globs: {e}")))?;
+
+        let mut walker = WalkBuilder::new(path);
+        walker
+            .standard_filters(false)
+            .add_custom_ignore_filename(IGNORE_FILE)
+            .overrides(overrides);
+        if let Some(frontend) = Pack::nested_frontend(path, config) {
+            walker.filter_entry(move |entry| entry.path() != frontend);
+        }
+        Pack::list_files(&mut walker)
+    }
+
+    /**
+     * A frontend inside the include directory is packed on its own, so it's left out of the backend
+     */
+    fn nested_frontend(path: &Path, config: &Config) -> Option<PathBuf> {
+        let root = path.canonicalize().ok()?;
+        let frontend = config.frontend.as_ref()?.canonicalize().ok()?;
+        let relative = frontend.strip_prefix(&root).ok()?;
+        if relative.as_os_str().is_empty() {
+            return None;
+        }
+        Some(path.join(relative))
+    }
+
+    /**
+     * Files in name order, so the pack doesn't depend on the order of the file system
+     */
+    fn list_files(walker: &mut WalkBuilder) -> Result<Vec<PathBuf>, EmbedError> {
+        let mut files = vec![];
+        let walker = walker
+            .follow_links(true)
+            .sort_by_file_name(|a, b| a.cmp(b))
+            .build();
+        for entry in walker {
+            let entry = entry.map_err(|e| EmbedError(format!("Failed to read directory: {e}")))?;
+            if entry
+                .file_type()
+                .is_some_and(|file_type| file_type.is_file())
+            {
+                files.push(entry.into_path());
+            }
+        }
+        Ok(files)
+    }
+
+    /**
+     * With an mtime the owner, permissions and time of the file are normalized,
+     * so the same files always give the same pack
+     */
+    fn append(
+        ar: &mut Builder<Vec<u8>>,
+        path: &Path,
+        name: &Path,
+        mtime: Option<u64>,
+    ) -> io::Result<()> {
+        let Some(mtime) = mtime else {
+            return ar.append_path_with_name(path, name);
+        };
+        let mut header = Header::new_gnu();
+        header.set_metadata_in_mode(&fs::metadata(path)?, HeaderMode::Deterministic);
+        header.set_mtime(mtime);
+        ar.append_data(&mut header, name, File::open(path)?)
+    }
+
+    fn index(tar: &[u8]) -> Result<Vec<PackEntry>, EmbedError> {
+        let mut archive = Archive::new(tar);
+        let entries = archive
+            .entries()
+            .map_err(|_| EmbedError("Failed to read pack".into()))?;
+        let mut files = vec![];
+        for entry in entries {
+            let entry = entry.map_err(|_| EmbedError("Failed to read pack entry".into()))?;
+            if entry.header().entry_type() != EntryType::Regular {
+                continue;
+            }
+            let path = entry
+                .path()
+                .map_err(|_| EmbedError("Failed to read pack entry path".into()))?;
+            files.push(PackEntry {
+                path: path.to_string_lossy().to_string(),
+                size: entry.size(),
+            });
+        }
+        Ok(files)
+    }
+
+    fn compress(data: Vec<u8>, compression: Compression) -> Result<Vec<u8>, EmbedError> {
+        match compression {
+            Compression::None => Ok(data),
+            Compression::Zstd(level) => zstd::encode_all(data.as_slice(), level)
+                .map_err(|_| EmbedError("Failed to compress pack".into())),
+        }
+    }
+}
+
+/**
+ * Checked here as well, the project file doesn't go through the range check of the CLI
+ */
+fn get_compression(config: &Config) -> Result<Compression, EmbedError> {
+    match config.compression {
+        0 => Ok(Compression::None),
+        level @ 1..=22 => Ok(Compression::Zstd(level)),
+        level => Err(EmbedError(format!(
+            "Invalid compression level {level}, expected 0 to 22"
+        ))),
+    }
+}
+
+/**
+ * The time set on every packed file with --reproducible, taken from SOURCE_DATE_EPOCH when set
+ */
+fn get_mtime(config: &Config) -> Result<Option<u64>, EmbedError> {
+    if !config.reproducible {
+        return Ok(None);
+    }
+    match env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => epoch
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|_| EmbedError(format!("Invalid SOURCE_DATE_EPOCH: {epoch}"))),
+        Err(_) => Ok(Some(0)),
+    }
+}
+
+pub fn hash(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env, fs,
+        path::{Path, PathBuf},
+    };
+
+    use clap::Parser;
+
+    use super::{get_compression, Pack};
+    use crate::{shared::payload::Compression, Config};
+
+    /**
+     * A project with sources, a build output and a dependency folder
+     */
+    fn project(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("deutron-test-{}-{name}", std::process::id()));
+        for file in [
+            "main.js",
+            "debug.log",
+            "web/index.html",
+            "web/app.ts",
+            "node_modules/dep/index.js",
+        ] {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, file).unwrap();
+        }
+        dir
+    }
+
+    fn packed(dir: &Path, args: &[&str]) -> Vec<String> {
+        let mut config = Config::parse_from(["deutron"].iter().chain(args).chain(&["node"]));
+        config.include = dir.to_path_buf();
+        let pack = Pack::new(&config.include, &config).unwrap();
+        pack.info.files.into_iter().map(|file| file.path).collect()
+    }
+
+    #[test]
+    fn compression_levels() {
+        let mut config = Config::parse_from(["deutron", "node"]);
+        assert_eq!(get_compression(&config).unwrap(), Compression::Zstd(19));
+        config.compression = 0;
+        assert_eq!(get_compression(&config).unwrap(), Compression::None);
+        // Set by a project file, which skips the range check of the CLI
+        for level in [-5, 23, 40] {
+            config.compression = level;
+            assert!(get_compression(&config).is_err(), "{level}");
+        }
+    }
+
+    #[test]
+    fn packs_everything_by_default() {
+        let dir = project("all");
+        assert_eq!(
+            packed(&dir, &[]),
+            [
+                "debug.log",
+                "main.js",
+                "node_modules/dep/index.js",
+                "web/app.ts",
+                "web/index.html"
+            ]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn globs() {
+        let dir = project("globs");
+        assert_eq!(
+            packed(&dir, &["--exclude", "*.log", "--exclude", "node_modules"]),
+            ["main.js", "web/app.ts", "web/index.html"]
+        );
+        assert_eq!(
+            packed(&dir, &["--include-glob", "web/**"]),
+            ["web/app.ts", "web/index.html"]
+        );
+        assert_eq!(
+            packed(
+                &dir,
+                &["--include-glob", "*.js", "--exclude", "node_modules"]
+            ),
+            ["main.js"]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn ignore_file() {
+        let dir = project("ignore");
+        fs::write(dir.join(".deutronignore"), "*.log\nnode_modules/\n").unwrap();
+        fs::write(dir.join("web/.deutronignore"), "*.ts\n").unwrap();
+        assert_eq!(
+            packed(&dir, &[]),
+            [
+                ".deutronignore",
+                "main.js",
+                "web/.deutronignore",
+                "web/index.html"
+            ]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,117 @@
+use std::{
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    base::Base,
+    package::{read_image, to_pngs},
+    shared::embed_error::EmbedError,
+};
+
+static ICON_SIZES: [u32; 7] = [16, 32, 48, 64, 128, 256, 512];
+
+/**
+ * Writes an AppDir: the binary in usr/bin, a .desktop entry and hicolor icons,
+ * which appimagetool turns into an AppImage as is
+ */
+pub fn write_appdir(
+    base: &mut Base,
+    out_path: &Path,
+    id: &str,
+    version: &str,
+    terminal: bool,
+    icon: Option<&Path>,
+) -> Result<PathBuf, EmbedError> {
+    let name = out_path.file_name().unwrap().to_string_lossy().to_string();
+    let mut app_dir = OsString::from(out_path);
+    app_dir.push(".AppDir");
+    let app_dir = PathBuf::from(app_dir);
+    if app_dir.exists() {
+        if !app_dir.join("AppRun").exists() {
+            return Err(EmbedError(format!("{app_dir:?} is not an AppDir")));
+        }
+        fs::remove_dir_all(&app_dir).map_err(|_| {
+            EmbedError("Can't write AppDir, make sure the application is closed".into())
+        })?;
+    }
+    let bin = app_dir.join("usr/bin");
+    let applications = app_dir.join("usr/share/applications");
+    fs::create_dir_all(&bin)
+        .and_then(|_| fs::create_dir_all(&applications))
+        .map_err(|_| EmbedError("Unable to create AppDir".into()))?;
+
+    let has_icon = match icon {
+        Some(icon) => {
+            let data = fs::read(icon).map_err(|_| EmbedError("Unable to read icon".into()))?;
+            write_icons(&app_dir, id, &data)?;
+            true
+        }
+        None => false,
+    };
+    let entry = desktop_entry(&name, id, version, terminal, has_icon);
+    let desktop_file = format!("{id}.desktop");
+    fs::write(app_dir.join(&desktop_file), &entry)
+        .and_then(|_| fs::write(applications.join(&desktop_file), &entry))
+        .map_err(|_| EmbedError("Unable to write desktop entry".into()))?;
+
+    base.write(bin.join(&name))?;
+    let app_run = app_dir.join("AppRun");
+    fs::write(&app_run, app_run_script(&name))
+        .map_err(|_| EmbedError("Unable to write AppRun".into()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&app_run, fs::Permissions::from_mode(0o755))
+            .map_err(|_| EmbedError("Unable to make AppRun executable".into()))?;
+    }
+    Ok(app_dir)
+}
+
+/**
+ * The hicolor theme icons, plus the top level icon and .DirIcon AppImages use
+ */
+fn write_icons(app_dir: &Path, id: &str, data: &[u8]) -> Result<(), EmbedError> {
+    let pngs = to_pngs(&read_image(data)?, &ICON_SIZES)?;
+    for (size, png) in &pngs {
+        let dir = app_dir.join(format!("usr/share/icons/hicolor/{size}x{size}/apps"));
+        fs::create_dir_all(&dir)
+            .and_then(|_| fs::write(dir.join(format!("{id}.png")), png))
+            .map_err(|_| EmbedError("Unable to write icon".into()))?;
+    }
+    let (_, largest) = pngs.last().unwrap();
+    fs::write(app_dir.join(format!("{id}.png")), largest)
+        .and_then(|_| fs::write(app_dir.join(".DirIcon"), largest))
+        .map_err(|_| EmbedError("Unable to write icon".into()))
+}
+
+fn desktop_entry(name: &str, id: &str, version: &str, terminal: bool, has_icon: bool) -> String {
+    let icon = if has_icon {
+        format!("Icon={id}\n")
+    } else {
+        String::new()
+    };
+    // StartupWMClass matches the window class, which is the binary name,
+    // so the taskbar shows the icon of this entry
+    format!(
+        "[Desktop Entry]
+Type=Application
+Name={name}
+Exec=\"{name}\"
+{icon}Terminal={terminal}
+Categories=Utility;
+StartupWMClass={name}
+X-AppImage-Version={version}
+"
+    )
+}
+
+fn app_run_script(name: &str) -> String {
+    format!(
+        "#!/bin/sh
+HERE=\"$(dirname \"$(readlink -f \"$0\")\")\"
+exec \"$HERE/usr/bin/{name}\" \"$@\"
+"
+    )
+}
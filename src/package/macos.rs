@@ -0,0 +1,192 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    base::Base,
+    package::{read_image, to_pngs},
+    shared::embed_error::EmbedError,
+};
+
+/**
+ * PNG based icns entries and the size of their image
+ */
+static ICNS_TYPES: [(&[u8; 4], u32); 10] = [
+    (b"icp4", 16),
+    (b"icp5", 32),
+    (b"ic11", 32),
+    (b"ic12", 64),
+    (b"ic07", 128),
+    (b"ic13", 256),
+    (b"ic08", 256),
+    (b"ic14", 512),
+    (b"ic09", 512),
+    (b"ic10", 1024),
+];
+static ICON_NAME: &str = "AppIcon";
+
+/**
+ * Writes a .app bundle: Contents/MacOS holds the binary, Contents/Resources the icon
+ */
+pub fn write_app(
+    base: &mut Base,
+    app_path: &Path,
+    id: &str,
+    version: &str,
+    icon: Option<&Path>,
+) -> Result<PathBuf, EmbedError> {
+    let name = app_path.file_stem().unwrap().to_string_lossy().to_string();
+    if app_path.exists() {
+        if !app_path.join("Contents/Info.plist").exists() {
+            return Err(EmbedError(format!("{app_path:?} is not an app bundle")));
+        }
+        fs::remove_dir_all(app_path).map_err(|_| {
+            EmbedError("Can't write app bundle, make sure the application is closed".into())
+        })?;
+    }
+    let contents = app_path.join("Contents");
+    let resources = contents.join("Resources");
+    fs::create_dir_all(contents.join("MacOS"))
+        .and_then(|_| fs::create_dir_all(&resources))
+        .map_err(|_| EmbedError("Unable to create app bundle".into()))?;
+
+    let has_icon = match icon {
+        Some(icon) => {
+            let data = fs::read(icon).map_err(|_| EmbedError("Unable to read icon".into()))?;
+            fs::write(resources.join(format!("{ICON_NAME}.icns")), to_icns(&data)?)
+                .map_err(|_| EmbedError("Unable to write icon".into()))?;
+            true
+        }
+        None => false,
+    };
+    fs::write(
+        contents.join("Info.plist"),
+        info_plist(&name, id, version, has_icon),
+    )
+    .map_err(|_| EmbedError("Unable to write Info.plist".into()))?;
+    base.write(contents.join("MacOS").join(&name))?;
+    Ok(app_path.to_path_buf())
+}
+
+fn info_plist(name: &str, id: &str, version: &str, has_icon: bool) -> String {
+    let icon = if has_icon {
+        format!("\t<key>CFBundleIconFile</key>\n\t<string>{ICON_NAME}</string>\n")
+    } else {
+        String::new()
+    };
+    // Bundle identifiers only allow letters, digits, '.' and '-'
+    let id = id.replace('_', "-");
+    let (name, version) = (escape(name), escape(version));
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+	<key>CFBundleDevelopmentRegion</key>
+	<string>en</string>
+	<key>CFBundleDisplayName</key>
+	<string>{name}</string>
+	<key>CFBundleExecutable</key>
+	<string>{name}</string>
+{icon}	<key>CFBundleIdentifier</key>
+	<string>{id}</string>
+	<key>CFBundleInfoDictionaryVersion</key>
+	<string>6.0</string>
+	<key>CFBundleName</key>
+	<string>{name}</string>
+	<key>CFBundlePackageType</key>
+	<string>APPL</string>
+	<key>CFBundleShortVersionString</key>
+	<string>{version}</string>
+	<key>CFBundleVersion</key>
+	<string>{version}</string>
+	<key>NSHighResolutionCapable</key>
+	<true/>
+</dict>
+</plist>
+"#
+    )
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/**
+ * Converts an image (ico, png, ...) to icns, only sizes up to that of the image are included
+ */
+fn to_icns(data: &[u8]) -> Result<Vec<u8>, EmbedError> {
+    let sizes: Vec<u32> = ICNS_TYPES.iter().map(|(_, size)| *size).collect();
+    let pngs = to_pngs(&read_image(data)?, &sizes)?;
+
+    let mut entries = vec![];
+    for ((kind, _), (_, png)) in ICNS_TYPES.iter().zip(pngs) {
+        entries.extend_from_slice(*kind);
+        entries.extend_from_slice(&(png.len() as u32 + 8).to_be_bytes());
+        entries.extend_from_slice(&png);
+    }
+
+    let mut icns = b"icns".to_vec();
+    icns.extend_from_slice(&(entries.len() as u32 + 8).to_be_bytes());
+    icns.extend_from_slice(&entries);
+    Ok(icns)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::{ImageFormat, Rgba, RgbaImage};
+
+    use super::{to_icns, ICNS_TYPES};
+
+    fn png(size: u32) -> Vec<u8> {
+        let mut png = vec![];
+        RgbaImage::from_pixel(size, size, Rgba([255, 0, 0, 255]))
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        png
+    }
+
+    /**
+     * The kind and data of each entry
+     */
+    fn entries(icns: &[u8]) -> Vec<(&[u8], &[u8])> {
+        let mut entries = vec![];
+        let mut rest = &icns[8..];
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes(rest[4..8].try_into().unwrap()) as usize;
+            entries.push((&rest[..4], &rest[8..len]));
+            rest = &rest[len..];
+        }
+        entries
+    }
+
+    #[test]
+    fn icns_entries() {
+        let icns = to_icns(&png(64)).unwrap();
+        assert_eq!(&icns[..4], b"icns");
+        assert_eq!(
+            u32::from_be_bytes(icns[4..8].try_into().unwrap()) as usize,
+            icns.len()
+        );
+        let entries = entries(&icns);
+        let kinds: Vec<&[u8]> = entries.iter().map(|(kind, _)| *kind).collect();
+        // Only sizes up to that of the image
+        assert_eq!(kinds, [b"icp4", b"icp5", b"ic11", b"ic12"]);
+        for ((_, data), (_, size)) in entries.iter().zip(ICNS_TYPES) {
+            let image = image::load_from_memory(data).unwrap();
+            assert_eq!((image.width(), image.height()), (size, size));
+        }
+    }
+
+    #[test]
+    fn small_images_keep_the_smallest_size() {
+        let entries = entries(&to_icns(&png(8)).unwrap()).len();
+        assert_eq!(entries, 1);
+        assert!(to_icns(b"not an image").is_err());
+    }
+}
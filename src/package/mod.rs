@@ -0,0 +1,30 @@
+use std::io::Cursor;
+
+use image::{imageops::FilterType, DynamicImage, ImageFormat};
+
+use crate::shared::embed_error::EmbedError;
+
+pub mod linux;
+pub mod macos;
+
+pub fn read_image(data: &[u8]) -> Result<DynamicImage, EmbedError> {
+    image::load_from_memory(data).map_err(|_| EmbedError("Unable to read icon".into()))
+}
+
+/**
+ * Square PNG versions of an image, skipping sizes larger than the image itself
+ * (the smallest size is always included)
+ */
+pub fn to_pngs(image: &DynamicImage, sizes: &[u32]) -> Result<Vec<(u32, Vec<u8>)>, EmbedError> {
+    let largest = image.width().max(image.height()).max(sizes[0]);
+    let mut pngs = vec![];
+    for &size in sizes.iter().filter(|size| **size <= largest) {
+        let mut png = vec![];
+        image
+            .resize_exact(size, size, FilterType::Lanczos3)
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .map_err(|_| EmbedError("Unable to convert icon".into()))?;
+        pngs.push((size, png));
+    }
+    Ok(pngs)
+}
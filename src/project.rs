@@ -0,0 +1,293 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use clap::{parser::ValueSource, ArgMatches, CommandFactory, FromArgMatches};
+use serde::Deserialize;
+
+use crate::{shared::embed_error::EmbedError, Config};
+
+static PROJECT_FILE: &str = "deutron.toml";
+static PACKAGE_FILE: &str = "package.json";
+
+/**
+ * Every Config field, as set by deutron.toml or the "deutron" key of package.json
+ */
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ProjectConfig {
+    pub debug: Option<bool>,
+    pub out: Option<String>,
+    pub set_version: Option<String>,
+    pub terminal: Option<bool>,
+    #[serde(alias = "backend")]
+    pub include: Option<PathBuf>,
+    pub frontend: Option<PathBuf>,
+    pub include_glob: Option<Vec<String>>,
+    pub exclude: Option<Vec<String>>,
+    pub extract_dir: Option<String>,
+    /**
+     * A table of variables instead of the KEY=VALUE list of the CLI
+     */
+    pub env: Option<BTreeMap<String, String>>,
+    pub cwd: Option<PathBuf>,
+    pub restart_on: Option<Vec<String>>,
+    pub frontend_url: Option<String>,
+    pub compression: Option<i32>,
+    pub reproducible: Option<bool>,
+    pub icon: Option<PathBuf>,
+    pub no_cache: Option<bool>,
+    pub dev_tools: Option<bool>,
+    pub verbose: Option<bool>,
+    pub product_name: Option<String>,
+    pub description: Option<String>,
+    pub company: Option<String>,
+    pub copyright: Option<String>,
+    pub app_id: Option<String>,
+    pub appdir: Option<bool>,
+    pub bundle_interpreter: Option<bool>,
+    pub bundle_file: Option<Vec<PathBuf>>,
+    pub runtime: Option<PathBuf>,
+    pub target: Option<String>,
+    pub command: Option<Vec<String>>,
+}
+
+/**
+ * Parses the CLI and fills in everything it didn't set from the project file
+ */
+pub fn load_config() -> Result<Config, EmbedError> {
+    let matches = Config::command().get_matches();
+    let mut config = Config::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    if config.action.is_none() {
+        if let Some(project) = ProjectConfig::load(&config.include)? {
+            project.merge(&mut config, &matches);
+        }
+    }
+    Ok(config)
+}
+
+impl ProjectConfig {
+    pub fn load(include: &Path) -> Result<Option<ProjectConfig>, EmbedError> {
+        let project_path = include.join(PROJECT_FILE);
+        if project_path.exists() {
+            let text = fs::read_to_string(&project_path)
+                .map_err(|_| EmbedError(format!("Unable to read {PROJECT_FILE}")))?;
+            let project =
+                toml::from_str(&text).map_err(|e| EmbedError(format!("{PROJECT_FILE}: {e}")))?;
+            return Ok(Some(project));
+        }
+
+        let package_path = include.join(PACKAGE_FILE);
+        if !package_path.exists() {
+            return Ok(None);
+        }
+        let text = fs::read_to_string(&package_path)
+            .map_err(|_| EmbedError(format!("Unable to read {PACKAGE_FILE}")))?;
+        let mut package: serde_json::Value =
+            serde_json::from_str(&text).map_err(|e| EmbedError(format!("{PACKAGE_FILE}: {e}")))?;
+        match package.get_mut("deutron").map(serde_json::Value::take) {
+            Some(value) => serde_json::from_value(value)
+                .map(Some)
+                .map_err(|e| EmbedError(format!("{PACKAGE_FILE} \"deutron\": {e}"))),
+            None => Ok(None),
+        }
+    }
+
+    pub fn merge(self, config: &mut Config, matches: &ArgMatches) {
+        let from_cli = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+        macro_rules! merge {
+            ($($field:ident),*) => {$(
+                if let Some(value) = self.$field {
+                    if !from_cli(stringify!($field)) {
+                        config.$field = value.into();
+                    }
+                }
+            )*};
+        }
+        merge!(
+            debug,
+            out,
+            set_version,
+            terminal,
+            include_glob,
+            exclude,
+            extract_dir,
+            cwd,
+            restart_on,
+            compression,
+            reproducible,
+            no_cache,
+            dev_tools,
+            verbose,
+            product_name,
+            description,
+            company,
+            copyright,
+            app_id,
+            appdir,
+            bundle_interpreter,
+            target,
+            command
+        );
+        // Only the dev loop uses it, so a project file keeping it can still be compiled
+        if let Some(frontend_url) = self.frontend_url {
+            if config.debug && !from_cli("frontend_url") {
+                config.frontend_url = Some(frontend_url);
+            }
+        }
+        if let Some(env) = self.env {
+            if !from_cli("env") {
+                config.env = env
+                    .iter()
+                    .map(|(key, value)| format!("{key}={value}"))
+                    .collect();
+            }
+        }
+        // Relative to the directory holding the project file
+        let dir = config.include.clone();
+        if let Some(include) = self.include {
+            if !from_cli("include") {
+                config.include = dir.join(include);
+            }
+        }
+        if let Some(frontend) = self.frontend {
+            if !from_cli("frontend") {
+                config.frontend = Some(dir.join(frontend));
+            }
+        }
+        if let Some(runtime) = self.runtime {
+            if !from_cli("runtime") {
+                config.runtime = Some(dir.join(runtime));
+            }
+        }
+        if let Some(icon) = self.icon {
+            if !from_cli("icon") {
+                config.icon = Some(dir.join(icon));
+            }
+        }
+        if let Some(files) = self.bundle_file {
+            if !from_cli("bundle_file") {
+                config.bundle_file = files.iter().map(|file| dir.join(file)).collect();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::PathBuf};
+
+    use clap::{CommandFactory, FromArgMatches};
+
+    use super::ProjectConfig;
+    use crate::Config;
+
+    /**
+     * The config of the command line args with the project file merged in
+     */
+    fn merged(args: &[&str], project: &str) -> Config {
+        let matches = Config::command().get_matches_from(["deutron"].iter().chain(args));
+        let mut config = Config::from_arg_matches(&matches).unwrap();
+        let project: ProjectConfig = toml::from_str(project).unwrap();
+        project.merge(&mut config, &matches);
+        config
+    }
+
+    #[test]
+    fn frontend_url_only_in_debug() {
+        let project = r#"frontend-url = "http://localhost:5173""#;
+        assert_eq!(merged(&["node"], project).frontend_url, None);
+        assert_eq!(
+            merged(&["--debug", "node"], project)
+                .frontend_url
+                .as_deref(),
+            Some("http://localhost:5173")
+        );
+        let debug_project = format!("debug = true\n{project}");
+        assert!(merged(&["node"], &debug_project).frontend_url.is_some());
+    }
+
+    #[test]
+    fn project_fills_in_defaults() {
+        let config = merged(
+            &["node"],
+            r#"
+                out = "app"
+                compression = 3
+                exclude = ["*.log"]
+                command = ["deno", "run", "main.ts"]
+                [env]
+                PORT = "8080"
+            "#,
+        );
+        assert_eq!(config.out.as_deref(), Some("app"));
+        assert_eq!(config.compression, 3);
+        assert_eq!(config.exclude, ["*.log"]);
+        assert_eq!(config.env, ["PORT=8080"]);
+        // Set on the command line as well, the command line wins
+        assert_eq!(config.command, ["node"]);
+    }
+
+    #[test]
+    fn command_line_wins() {
+        let project = r#"
+            out = "project"
+            compression = 3
+            env = { A = "project" }
+        "#;
+        let config = merged(
+            &[
+                "--out",
+                "cli",
+                "--compression",
+                "19",
+                "--env",
+                "A=cli",
+                "node",
+            ],
+            project,
+        );
+        assert_eq!(config.out.as_deref(), Some("cli"));
+        // Equal to the default, but still given on the command line
+        assert_eq!(config.compression, 19);
+        assert_eq!(config.env, ["A=cli"]);
+    }
+
+    #[test]
+    fn paths_relative_to_project() {
+        let config = merged(
+            &["--include", "project", "node"],
+            r#"
+                frontend = "web"
+                icon = "icon.png"
+                bundle-file = ["lib"]
+            "#,
+        );
+        let dir = PathBuf::from("project");
+        assert_eq!(config.frontend, Some(dir.join("web")));
+        assert_eq!(config.icon, Some(dir.join("icon.png")));
+        assert_eq!(config.bundle_file, [dir.join("lib")]);
+    }
+
+    #[test]
+    fn package_json() {
+        let dir = env::temp_dir().join(format!("deutron-test-{}-package", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("package.json"),
+            r#"{ "name": "app", "deutron": { "out": "app", "backend": "server" } }"#,
+        )
+        .unwrap();
+        let project = ProjectConfig::load(&dir).unwrap().unwrap();
+        assert_eq!(project.out.as_deref(), Some("app"));
+        assert_eq!(project.include, Some(PathBuf::from("server")));
+
+        fs::write(dir.join("package.json"), r#"{ "name": "app" }"#).unwrap();
+        assert!(ProjectConfig::load(&dir).unwrap().is_none());
+        fs::write(dir.join("deutron.toml"), "unknown = 1").unwrap();
+        assert!(ProjectConfig::load(&dir).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,11 +1,20 @@
 use crate::backend::Backend;
 use crate::cleanup::cleanup_onexit;
 use crate::shared::embed_error::{EmbedError, UnwrapPrintln};
-use crate::temp::get_temp_dir;
+use crate::shared::payload::INTERPRETER_DIR;
+use crate::temp::{
+    cleanup_pack_dirs, get_app_dir, get_pack_dir, get_webview_dir, migrate_webview_dir,
+};
+use crate::webview::vfs::{ArchiveFs, DiskFs, Vfs};
 use crate::{app::App, state::State};
 use clap::{command, Parser};
 use serde::{Deserialize, Serialize};
-use std::{env, path::PathBuf, sync::Mutex};
+use std::{
+    env, fs,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    thread,
+};
 
 #[macro_use]
 mod macros;
@@ -42,23 +51,62 @@ pub struct Config {
     pub command: Vec<String>,
 }
 
+/**
+ * Lets the compiler recognize this binary as a runtime when it's passed with --runtime
+ */
+static RUNTIME_MARKER: &str = concat!("DEUTRON_RUNTIME_FORMAT=", format_version!(), ";");
+
 fn main() {
+    std::hint::black_box(RUNTIME_MARKER);
     cleanup_onexit();
     let manager = webview::webview_manager::WebViewManager::new();
-    manager.start(Some(vec![]));
+    manager.start(Some(vec![]), |_| {});
 }
 
 fn start() -> Result<State, EmbedError> {
     let mut embedded = App::load().unwrap_or_println();
-    let mut command = embedded.take_strings()?;
-    let version = embedded.take_string()?;
-    let dev_tools = embedded.take_sized::<bool>();
-    let no_cache = embedded.take_sized::<bool>();
-    let verbose = embedded.take_sized::<bool>();
+    let manifest = embedded.manifest().clone();
+    let mut command = manifest.command;
+    let verbose = manifest.flags.verbose;
+    let dev_tools = manifest.flags.dev_tools;
 
-    let backend_dir = get_temp_dir(&version, "backend")?;
-    let webview_dir = get_temp_dir(&version, "webview")?;
-    embedded.take_pack(&backend_dir, !no_cache)?;
+    let app_dir = get_app_dir(&manifest.id, &manifest.extract_dir)?;
+    let backend_dir = get_pack_dir(&app_dir, &manifest.pack.hash);
+    let webview_dir = get_webview_dir(&app_dir);
+    // Only the first start with this layout takes over the data of older deutron versions
+    if !app_dir.exists() {
+        migrate_webview_dir(&webview_dir);
+    }
+    let cache = !manifest.flags.no_cache;
+    let mut pack_locks = vec![embedded.take_pack(&manifest.pack, &backend_dir, cache)?];
+    if let Some(interpreter) = &manifest.interpreter {
+        let interpreter_dir = backend_dir.join(INTERPRETER_DIR);
+        pack_locks.push(embedded.take_pack(&interpreter.pack, &interpreter_dir, cache)?);
+        if let Some(exe) = command.first_mut() {
+            *exe = interpreter_dir
+                .join(&interpreter.exe)
+                .to_string_lossy()
+                .to_string();
+        }
+    }
+    // Without a separate frontend the webviews are served the unpacked backend
+    let files: Arc<dyn Vfs> = match &manifest.frontend {
+        Some(frontend) => Arc::new(
+            ArchiveFs::new(embedded.exe()?, frontend.clone())
+                .map_err(|_| EmbedError("Unable to read frontend pack".into()))?,
+        ),
+        None => Arc::new(DiskFs(backend_dir.clone())),
+    };
+    let backend_env = manifest.env;
+    let backend_cwd = match &manifest.cwd {
+        Some(cwd) => backend_dir.join(cwd),
+        None => backend_dir.clone(),
+    };
+    // Empty folders aren't packed, so the working directory may not be unpacked
+    fs::create_dir_all(&backend_cwd)
+        .map_err(|_| EmbedError("Unable to create the backend working directory".into()))?;
+    let current_dir = backend_dir.clone();
+    thread::spawn(move || cleanup_pack_dirs(&current_dir));
 
     let mut args: Vec<String> = env::args().collect();
     args.remove(0);
@@ -66,17 +114,23 @@ fn start() -> Result<State, EmbedError> {
     if verbose {
         println!("Starting: {}", command.join(" "));
     }
-    let mut backend = Backend::new(command.clone(), &backend_dir);
+    let mut backend = Backend::new(command.clone(), &backend_cwd, &backend_env);
 
     Ok(State {
         verbose,
         dev_tools,
         icon: backend_dir.join("favicon.ico"),
+        files,
+        frontend_url: None,
+        pack_locks,
         backend_dir,
         webview_dir,
+        command,
+        backend_cwd,
+        backend_env,
         backend_in: Mutex::new(backend.get_stdin()),
-        backend_out: Mutex::new(backend.get_stdout()),
-        backend_err: Mutex::new(backend.get_stderr()),
+        backend_out: Mutex::new(Some(backend.get_stdout())),
+        backend_err: Mutex::new(Some(backend.get_stderr())),
         backend: Mutex::new(backend),
     })
 }
@@ -1,2 +1,3 @@
 pub mod embed_error;
 pub mod embed_trailer;
+pub mod payload;
@@ -0,0 +1,128 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+/**
+ * Layout of a compiled binary:
+ * [runtime][pack][manifest json][manifest len: u64 LE][format version: u32 LE][TRAILER]
+ *
+ * The format version is bumped whenever this layout or the manifest changes
+ * in a way a runtime built for an older version can't read.
+ */
+#[macro_export]
+macro_rules! format_version {
+    () => {
+        8
+    };
+}
+pub const FORMAT_VERSION: u32 = format_version!();
+/**
+ * Start of the marker every runtime embeds, followed by its format version and ';'
+ */
+pub const RUNTIME_MARKER_PREFIX: &[u8] = b"DEUTRON_RUNTIME_FORMAT=";
+pub const FOOTER_LEN: usize = size_of::<u64>() + size_of::<u32>();
+/**
+ * Written into an unpacked directory once extraction finished, holds the pack hash
+ */
+pub const PACK_MARKER: &str = ".deutron-pack";
+/**
+ * Directory inside the unpacked backend that holds the bundled interpreter
+ */
+pub const INTERPRETER_DIR: &str = ".deutron-interpreter";
+/**
+ * Appended to an unpack directory for the lock file guarding it
+ */
+pub const LOCK_SUFFIX: &str = ".lock";
+/**
+ * Appended to an unpack directory for the staging directory it is extracted into
+ */
+pub const STAGING_SUFFIX: &str = ".partial";
+/**
+ * Appended to an unpack directory for the lock file held by the instances running from it
+ */
+pub const RUNNING_SUFFIX: &str = ".running";
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Manifest {
+    pub id: String,
+    pub command: Vec<String>,
+    pub version: String,
+    pub flags: Flags,
+    pub pack: PackInfo,
+    /**
+     * Only the frontend pack is served to the webviews, when missing the backend pack is served
+     */
+    pub frontend: Option<PackInfo>,
+    pub interpreter: Option<Interpreter>,
+    pub extract_dir: ExtractDir,
+    /**
+     * Environment variables set for the backend
+     */
+    pub env: BTreeMap<String, String>,
+    /**
+     * Working directory of the backend inside the unpacked pack, separated by '/'
+     */
+    pub cwd: Option<String>,
+}
+
+/**
+ * Where the runtime unpacks the backend and keeps the webview data
+ */
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum ExtractDir {
+    Temp,
+    Cache,
+    Data,
+    /**
+     * Relative paths are relative to the executable
+     */
+    Path(String),
+}
+
+impl ExtractDir {
+    pub fn parse(value: &str) -> ExtractDir {
+        match value {
+            "temp" => ExtractDir::Temp,
+            "cache" => ExtractDir::Cache,
+            "data" => ExtractDir::Data,
+            path => ExtractDir::Path(path.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Flags {
+    pub verbose: bool,
+    pub no_cache: bool,
+    pub dev_tools: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PackInfo {
+    pub offset: u64,
+    pub len: u64,
+    pub compression: Compression,
+    pub hash: String,
+    pub files: Vec<PackEntry>,
+}
+
+/**
+ * The executable that replaces the first part of the command, and the pack holding it
+ */
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Interpreter {
+    pub exe: String,
+    pub pack: PackInfo,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PackEntry {
+    pub path: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum Compression {
+    None,
+    Zstd(i32),
+}
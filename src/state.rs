@@ -1,27 +1,57 @@
 use std::{
+    collections::BTreeMap,
+    fs::File,
     path::PathBuf,
-    sync::{LazyLock, Mutex},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, LazyLock, Mutex,
+    },
 };
 
 use crate::{
     backend::{Backend, ChildErr, ChildIn, ChildOut},
     shared::embed_error::UnwrapPrintln,
     start,
+    webview::vfs::Vfs,
 };
 
 pub static STATE: LazyLock<State> = LazyLock::new(|| {
     let state = start().unwrap_or_println();
+    STARTED.store(true, Ordering::SeqCst);
     return state;
 });
 
+/**
+ * Set once the backend is running, so exiting before that doesn't start it
+ */
+pub static STARTED: AtomicBool = AtomicBool::new(false);
+
 pub struct State {
     pub verbose: bool,
     pub dev_tools: bool,
     pub icon: PathBuf,
     pub backend_dir: PathBuf,
+    /**
+     * The backend command, used to start it again after a restart
+     */
+    pub command: Vec<String>,
+    pub backend_cwd: PathBuf,
+    pub backend_env: BTreeMap<String, String>,
     pub webview_dir: PathBuf,
+    pub files: Arc<dyn Vfs>,
+    /**
+     * Dev server the windows load from instead of the files, ends with a slash
+     */
+    pub frontend_url: Option<String>,
+    /**
+     * Keeps the unpacked packs from being replaced or removed while the backend runs
+     */
+    pub pack_locks: Vec<File>,
     pub backend: Mutex<Backend>,
     pub backend_in: Mutex<ChildIn>,
-    pub backend_out: Mutex<ChildOut>,
-    pub backend_err: Mutex<ChildErr>,
+    /**
+     * Taken by the listeners when the windows start
+     */
+    pub backend_out: Mutex<Option<ChildOut>>,
+    pub backend_err: Mutex<Option<ChildErr>>,
 }
@@ -0,0 +1,302 @@
+use std::{env, fmt};
+
+use crate::shared::embed_error::EmbedError;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Os {
+    Windows,
+    Linux,
+    Macos,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arch {
+    X86_64,
+    Aarch64,
+}
+
+/**
+ * A platform there is a runtime for
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Target {
+    pub os: Os,
+    pub arch: Arch,
+}
+
+impl Os {
+    const ALL: [Os; 3] = [Os::Windows, Os::Linux, Os::Macos];
+
+    fn parse(name: &str) -> Option<Os> {
+        match name {
+            "windows" => Some(Os::Windows),
+            "linux" => Some(Os::Linux),
+            "macos" | "darwin" => Some(Os::Macos),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Os::Windows => "windows",
+            Os::Linux => "linux",
+            Os::Macos => "macos",
+        }
+    }
+}
+
+impl Arch {
+    const ALL: [Arch; 2] = [Arch::X86_64, Arch::Aarch64];
+
+    fn parse(name: &str) -> Option<Arch> {
+        match name {
+            "x86_64" | "amd64" | "x64" => Some(Arch::X86_64),
+            "aarch64" | "arm64" => Some(Arch::Aarch64),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Arch::X86_64 => "x86_64",
+            Arch::Aarch64 => "aarch64",
+        }
+    }
+}
+
+impl Target {
+    pub fn host() -> Result<Target, EmbedError> {
+        let os = Os::parse(env::consts::OS);
+        let arch = Arch::parse(env::consts::ARCH);
+        match (os, arch) {
+            (Some(os), Some(arch)) => Ok(Target { os, arch }),
+            _ => Err(unsupported(&format!(
+                "{}-{}",
+                env::consts::OS,
+                env::consts::ARCH
+            ))),
+        }
+    }
+
+    pub fn all() -> Vec<Target> {
+        Os::ALL
+            .iter()
+            .flat_map(|os| Arch::ALL.map(|arch| Target { os: *os, arch }))
+            .collect()
+    }
+
+    /**
+     * Accepts an os, an os-arch pair or a target triple like aarch64-apple-darwin,
+     * an os alone uses the architecture of this machine when it runs that os and x86_64 otherwise
+     */
+    pub fn parse(name: &str) -> Result<Target, EmbedError> {
+        let lower = name.to_lowercase();
+        let parts: Vec<&str> = lower.split('-').collect();
+        let os = parts.iter().find_map(|part| Os::parse(part));
+        let arch = if parts.len() == 1 {
+            match Target::host() {
+                Ok(host) if Some(host.os) == os => Some(host.arch),
+                _ => Some(Arch::X86_64),
+            }
+        } else {
+            parts.iter().find_map(|part| Arch::parse(part))
+        };
+        match (os, arch) {
+            (Some(os), Some(arch)) => Ok(Target { os, arch }),
+            _ => Err(unsupported(name)),
+        }
+    }
+}
+
+/**
+ * The platforms an executable runs on, read from its ELF, PE or Mach-O header.
+ * A universal Mach-O binary runs on each of its architectures
+ */
+pub fn executable_targets(exe: &[u8]) -> Vec<Target> {
+    let u16_at = |at: usize, le: bool| {
+        let bytes: [u8; 2] = exe.get(at..at + 2)?.try_into().ok()?;
+        Some(if le {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        })
+    };
+    let u32_at = |at: usize, le: bool| {
+        let bytes: [u8; 4] = exe.get(at..at + 4)?.try_into().ok()?;
+        Some(if le {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    };
+    let target = |os, arch: Option<Arch>| arch.map(|arch| Target { os, arch });
+    let mach_arch = |cpu| match cpu {
+        0x0100_0007 => Some(Arch::X86_64),
+        0x0100_000c => Some(Arch::Aarch64),
+        _ => None,
+    };
+
+    if exe.starts_with(b"\x7fELF") {
+        let le = exe.get(5) == Some(&1);
+        let arch = match u16_at(18, le) {
+            Some(62) => Some(Arch::X86_64),
+            Some(183) => Some(Arch::Aarch64),
+            _ => None,
+        };
+        return target(Os::Linux, arch).into_iter().collect();
+    }
+    if exe.starts_with(b"MZ") {
+        let Some(pe) = u32_at(0x3c, true).map(|at| at as usize) else {
+            return vec![];
+        };
+        if exe.get(pe..pe + 4) != Some(b"PE\0\0") {
+            return vec![];
+        }
+        let arch = match u16_at(pe + 4, true) {
+            Some(0x8664) => Some(Arch::X86_64),
+            Some(0xaa64) => Some(Arch::Aarch64),
+            _ => None,
+        };
+        return target(Os::Windows, arch).into_iter().collect();
+    }
+    match u32_at(0, true) {
+        Some(0xfeed_facf) => target(Os::Macos, u32_at(4, true).and_then(mach_arch))
+            .into_iter()
+            .collect(),
+        // Universal binaries list their architectures big endian
+        Some(0xbeba_feca) => {
+            let count = u32_at(4, false).unwrap_or(0) as usize;
+            (0..count)
+                .filter_map(|i| target(Os::Macos, u32_at(8 + i * 20, false).and_then(mach_arch)))
+                .collect()
+        }
+        _ => vec![],
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.os.name(), self.arch.name())
+    }
+}
+
+fn unsupported(name: &str) -> EmbedError {
+    let supported: Vec<String> = Target::all().iter().map(Target::to_string).collect();
+    EmbedError(format!(
+        "Unsupported target \"{name}\", supported targets are: {} (or the matching target triple)",
+        supported.join(", ")
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{executable_targets, Arch, Os, Target};
+
+    fn parse(name: &str) -> Option<(Os, Arch)> {
+        Target::parse(name)
+            .ok()
+            .map(|target| (target.os, target.arch))
+    }
+
+    #[test]
+    fn os_and_arch() {
+        assert_eq!(parse("linux-aarch64"), Some((Os::Linux, Arch::Aarch64)));
+        assert_eq!(parse("macos-arm64"), Some((Os::Macos, Arch::Aarch64)));
+        assert_eq!(parse("windows-x64"), Some((Os::Windows, Arch::X86_64)));
+        assert_eq!(parse("Linux-AMD64"), Some((Os::Linux, Arch::X86_64)));
+    }
+
+    #[test]
+    fn target_triples() {
+        assert_eq!(
+            parse("aarch64-apple-darwin"),
+            Some((Os::Macos, Arch::Aarch64))
+        );
+        assert_eq!(
+            parse("x86_64-pc-windows-msvc"),
+            Some((Os::Windows, Arch::X86_64))
+        );
+        assert_eq!(
+            parse("x86_64-unknown-linux-gnu"),
+            Some((Os::Linux, Arch::X86_64))
+        );
+    }
+
+    #[test]
+    fn bare_os() {
+        let host = Target::host().ok();
+        for os in [Os::Windows, Os::Linux, Os::Macos] {
+            let arch = match host {
+                Some(host) if host.os == os => host.arch,
+                _ => Arch::X86_64,
+            };
+            assert_eq!(parse(os.name()), Some((os, arch)));
+        }
+        assert_eq!(parse("darwin").map(|(os, _)| os), Some(Os::Macos));
+    }
+
+    #[test]
+    fn unsupported() {
+        for name in ["", "freebsd", "linux-x86", "i686-pc-windows-msvc"] {
+            assert_eq!(parse(name), None, "{name}");
+        }
+    }
+
+    #[test]
+    fn display_parses_back() {
+        for target in Target::all() {
+            assert_eq!(Target::parse(&target.to_string()).ok(), Some(target));
+        }
+    }
+
+    fn target(os: Os, arch: Arch) -> Target {
+        Target { os, arch }
+    }
+
+    #[test]
+    fn executable_headers() {
+        let mut elf = b"\x7fELF\x02\x01".to_vec();
+        elf.resize(20, 0);
+        elf[18] = 183;
+        assert_eq!(executable_targets(&elf), [target(Os::Linux, Arch::Aarch64)]);
+
+        let mut pe = b"MZ".to_vec();
+        pe.resize(0x40, 0);
+        pe[0x3c] = 0x40;
+        pe.extend_from_slice(b"PE\0\0");
+        pe.extend_from_slice(&0x8664u16.to_le_bytes());
+        assert_eq!(executable_targets(&pe), [target(Os::Windows, Arch::X86_64)]);
+
+        let mut macho = 0xfeed_facfu32.to_le_bytes().to_vec();
+        macho.extend_from_slice(&0x0100_000cu32.to_le_bytes());
+        assert_eq!(
+            executable_targets(&macho),
+            [target(Os::Macos, Arch::Aarch64)]
+        );
+
+        let mut universal = 0xcafe_babeu32.to_be_bytes().to_vec();
+        universal.extend_from_slice(&2u32.to_be_bytes());
+        for cpu in [0x0100_0007u32, 0x0100_000c] {
+            universal.extend_from_slice(&cpu.to_be_bytes());
+            universal.extend_from_slice(&[0; 16]);
+        }
+        assert_eq!(
+            executable_targets(&universal),
+            [
+                target(Os::Macos, Arch::X86_64),
+                target(Os::Macos, Arch::Aarch64)
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_executables() {
+        assert!(executable_targets(b"").is_empty());
+        assert!(executable_targets(b"#!/bin/sh\n").is_empty());
+        assert!(executable_targets(b"MZ").is_empty());
+        let mut i686 = b"\x7fELF\x01\x01".to_vec();
+        i686.resize(20, 0);
+        i686[18] = 3;
+        assert!(executable_targets(&i686).is_empty());
+    }
+}
@@ -1,22 +1,279 @@
 use std::{
-    env::{self, current_exe},
-    path::PathBuf,
+    collections::BTreeSet,
+    env,
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
 };
 
-use crate::shared::embed_error::EmbedError;
-
-pub fn get_temp_dir(version: &str, ext: &str) -> Result<PathBuf, EmbedError> {
-    let tempfile = format!(
-        "{}_{}_{}",
-        current_exe()
-            .map_err(|_| EmbedError("Unable to write executable".into()))?
-            .file_name()
-            .unwrap()
-            .to_str()
-            .unwrap(),
-        version,
-        ext
-    );
-    let temp = env::temp_dir();
-    Ok(temp.join(tempfile))
+use crate::shared::{
+    embed_error::EmbedError,
+    payload::{ExtractDir, LOCK_SUFFIX, PACK_MARKER, RUNNING_SUFFIX, STAGING_SUFFIX},
+};
+
+static HASH_LEN: usize = 16;
+static WEBVIEW_DIR: &str = "webview";
+
+/**
+ * The directory an application unpacks into, <base>/deutron/<id> unless a path was given
+ */
+pub fn get_app_dir(id: &str, extract_dir: &ExtractDir) -> Result<PathBuf, EmbedError> {
+    let base = match extract_dir {
+        ExtractDir::Temp => Some(env::temp_dir()),
+        ExtractDir::Cache => dirs::cache_dir(),
+        ExtractDir::Data => dirs::data_local_dir(),
+        ExtractDir::Path(path) => {
+            // Relative to the executable, so portable apps can keep their files next to it
+            let exe =
+                env::current_exe().map_err(|_| EmbedError("Couldn't find executable".into()))?;
+            return Ok(exe.parent().unwrap_or(Path::new("")).join(path));
+        }
+    };
+    Ok(base.unwrap_or_else(env::temp_dir).join("deutron").join(id))
+}
+
+pub fn get_webview_dir(app_dir: &Path) -> PathBuf {
+    app_dir.join(WEBVIEW_DIR)
+}
+
+/**
+ * Unpacked files are keyed by the pack hash, so a rebuild never reuses stale files
+ */
+pub fn get_pack_dir(app_dir: &Path, hash: &str) -> PathBuf {
+    app_dir.join(&hash[..HASH_LEN.min(hash.len())])
+}
+
+/**
+ * Locks the file at path, creating it if needed
+ */
+pub fn lock_file(path: &Path, shared: bool) -> io::Result<File> {
+    loop {
+        let lock = open_lock(path)?;
+        if shared {
+            lock.lock_shared()?;
+        } else {
+            lock.lock()?;
+        }
+        // Another build may have removed the file while this one waited for it
+        if path.exists() {
+            return Ok(lock);
+        }
+    }
+}
+
+/**
+ * Locks the file at path exclusively, unless someone else holds it
+ */
+pub fn try_lock_file(path: &Path) -> io::Result<Option<File>> {
+    loop {
+        let lock = open_lock(path)?;
+        if lock.try_lock().is_err() {
+            return Ok(None);
+        }
+        if path.exists() {
+            return Ok(Some(lock));
+        }
+    }
+}
+
+/**
+ * Removes directories unpacked by other builds of the application that aren't running,
+ * with the lock files and interrupted unpacks they left behind
+ */
+pub fn cleanup_pack_dirs(current: &Path) {
+    let Some(app_dir) = current.parent() else {
+        return;
+    };
+    let current_name = current.file_name().unwrap_or_default().to_string_lossy();
+    remove_pack_dirs(app_dir, &current_name);
+}
+
+/**
+ * Copies the webview data older deutron versions kept in <temp>/<exe>_<version>_webview,
+ * so local storage and cookies survive the update. Those directories may still be in use
+ * by an instance of an older version, so they're left for the system to clear with the temp directory
+ */
+pub fn migrate_webview_dir(webview_dir: &Path) {
+    let Some(exe) = env::current_exe().ok().and_then(|exe| {
+        exe.file_name()
+            .map(|name| name.to_string_lossy().to_string())
+    }) else {
+        return;
+    };
+    let Ok(entries) = fs::read_dir(env::temp_dir()) else {
+        return;
+    };
+    let latest = entries
+        .flatten()
+        .filter(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            legacy_version(&name, &exe, WEBVIEW_DIR).is_some()
+        })
+        .filter_map(|entry| {
+            let md = entry.metadata().ok().filter(|md| md.is_dir())?;
+            Some((entry.path(), md.modified().ok()?))
+        })
+        .max_by_key(|(_, modified)| *modified);
+    if let Some((legacy, _)) = latest {
+        copy_dir(&legacy, webview_dir).ok();
+    }
+}
+
+/**
+ * The version in <exe>_<version>_<kind>, how older deutron versions named their directories
+ */
+fn legacy_version<'a>(name: &'a str, exe: &str, kind: &str) -> Option<&'a str> {
+    let version = name
+        .strip_prefix(exe)?
+        .strip_prefix('_')?
+        .strip_suffix(kind)?
+        .strip_suffix('_')?;
+    Some(version).filter(|version| !version.is_empty() && !version.contains('_'))
+}
+
+fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+fn remove_pack_dirs(dir: &Path, current: &str) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut hashes = BTreeSet::new();
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let hash = [LOCK_SUFFIX, STAGING_SUFFIX, RUNNING_SUFFIX]
+            .iter()
+            .find_map(|suffix| name.strip_suffix(suffix))
+            .unwrap_or(&name);
+        let is_hash = hash.len() == HASH_LEN && hash.chars().all(|c| c.is_ascii_hexdigit());
+        if is_hash && hash != current {
+            hashes.insert(hash.to_string());
+        }
+    }
+    for hash in hashes {
+        remove_pack_dir(&dir.join(hash));
+    }
+}
+
+/**
+ * Skipped while an instance runs from the directory or unpacks it, starting ones wait until it's gone
+ */
+fn remove_pack_dir(pack_dir: &Path) {
+    let locks = [RUNNING_SUFFIX, LOCK_SUFFIX].map(|suffix| with_suffix(pack_dir, suffix));
+    let held: Vec<File> = locks
+        .iter()
+        .map_while(|path| open_lock(path).ok().filter(|lock| lock.try_lock().is_ok()))
+        .collect();
+    if held.len() < locks.len() {
+        return;
+    }
+    if pack_dir.join(PACK_MARKER).exists() {
+        fs::remove_dir_all(pack_dir).ok();
+    }
+    fs::remove_dir_all(with_suffix(pack_dir, STAGING_SUFFIX)).ok();
+    // Windows can't remove a file that is still open
+    let left: Vec<&PathBuf> = locks
+        .iter()
+        .filter(|path| fs::remove_file(path).is_err())
+        .collect();
+    drop(held);
+    for path in left {
+        fs::remove_file(path).ok();
+    }
+}
+
+fn open_lock(path: &Path) -> io::Result<File> {
+    File::options()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+}
+
+pub fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    path.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env, fs,
+        path::{Path, PathBuf},
+    };
+
+    use super::{legacy_version, lock_file, remove_pack_dirs, with_suffix};
+    use crate::shared::payload::{LOCK_SUFFIX, PACK_MARKER, RUNNING_SUFFIX, STAGING_SUFFIX};
+
+    #[test]
+    fn legacy_names() {
+        assert_eq!(
+            legacy_version("app_1.0_backend", "app", "backend"),
+            Some("1.0")
+        );
+        assert_eq!(
+            legacy_version("app.exe_2_webview", "app.exe", "webview"),
+            Some("2")
+        );
+        // Another app whose name starts with this one
+        assert_eq!(
+            legacy_version("app_tools_1.0_backend", "app", "backend"),
+            None
+        );
+        assert_eq!(
+            legacy_version("apptools_1.0_backend", "app", "backend"),
+            None
+        );
+        assert_eq!(legacy_version("app__backend", "app", "backend"), None);
+        assert_eq!(legacy_version("app_1.0_webview", "app", "backend"), None);
+        assert_eq!(legacy_version("app_backend", "app", "backend"), None);
+    }
+
+    fn pack_dir(dir: &Path, hash: &str) -> PathBuf {
+        let path = dir.join(hash);
+        fs::create_dir_all(&path).unwrap();
+        fs::write(path.join(PACK_MARKER), hash).unwrap();
+        path
+    }
+
+    #[test]
+    fn removes_other_builds() {
+        let dir = env::temp_dir().join(format!("deutron-test-{}-packs", std::process::id()));
+        let current = pack_dir(&dir, "0000000000000000");
+        let old = pack_dir(&dir, "1111111111111111");
+        fs::write(with_suffix(&old, LOCK_SUFFIX), "").unwrap();
+        let interrupted = with_suffix(&dir.join("2222222222222222"), STAGING_SUFFIX);
+        fs::create_dir_all(&interrupted).unwrap();
+        let running = pack_dir(&dir, "3333333333333333");
+        let lock = lock_file(&with_suffix(&running, RUNNING_SUFFIX), true).unwrap();
+        let webview = dir.join("webview");
+        fs::create_dir_all(&webview).unwrap();
+        let foreign = dir.join("4444444444444444");
+        fs::create_dir_all(&foreign).unwrap();
+
+        remove_pack_dirs(&dir, "0000000000000000");
+        assert!(current.exists());
+        assert!(!old.exists() && !with_suffix(&old, LOCK_SUFFIX).exists());
+        assert!(!interrupted.exists());
+        assert!(running.exists(), "a running build is kept");
+        assert!(webview.exists());
+        assert!(foreign.exists(), "only unpacked directories are removed");
+
+        drop(lock);
+        remove_pack_dirs(&dir, "0000000000000000");
+        assert!(!running.exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
 }
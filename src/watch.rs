@@ -0,0 +1,115 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+use ignore::overrides::{Override, OverrideBuilder};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use tao::event_loop::EventLoopProxy;
+
+use crate::{
+    shared::embed_error::EmbedError,
+    webview::enums::{
+        backend::backend_message::BackendMessage,
+        webview::{info::InfoMessage, webview_action::WebViewAction},
+    },
+    Config,
+};
+
+/**
+ * Editors often save a file in several writes, changes this close together are handled at once
+ */
+const SETTLE_TIME: Duration = Duration::from_millis(100);
+
+/**
+ * Reloads windows when files they loaded change, and restarts the backend when files matching --restart-on change
+ */
+pub fn watch(config: &Config, proxy: EventLoopProxy<WebViewAction>) -> Result<(), EmbedError> {
+    let canonicalize = |path: &Path| {
+        path.canonicalize()
+            .map_err(|_| EmbedError(format!("Unable to watch {path:?}")))
+    };
+    let backend_dir = canonicalize(&config.include)?;
+    let frontend_dir = canonicalize(config.frontend.as_ref().unwrap_or(&config.include))?;
+    let restart_on = restart_globs(&backend_dir, &config.restart_on)?;
+
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)
+        .map_err(|_| EmbedError("Unable to watch for changes".into()))?;
+    let mut dirs = vec![&backend_dir];
+    if !frontend_dir.starts_with(&backend_dir) {
+        dirs.push(&frontend_dir);
+    }
+    for dir in dirs {
+        watcher
+            .watch(dir, RecursiveMode::Recursive)
+            .map_err(|_| EmbedError(format!("Unable to watch {dir:?}")))?;
+    }
+
+    thread::spawn(move || {
+        // Watching stops when the watcher is dropped
+        let _watcher = watcher;
+        while let Ok(event) = receiver.recv() {
+            let mut changed = HashSet::new();
+            add_paths(event, &mut changed);
+            while let Ok(event) = receiver.recv_timeout(SETTLE_TIME) {
+                add_paths(event, &mut changed);
+            }
+
+            let restart = changed
+                .iter()
+                .filter(|path| path.starts_with(&backend_dir))
+                .any(|path| restart_on.matched(path, false).is_whitelist());
+            if restart {
+                unwrap_log!(WebViewAction::Restart.perform(&proxy));
+            }
+            let files: Vec<String> = changed
+                .iter()
+                .filter_map(|path| path.strip_prefix(&frontend_dir).ok())
+                .map(to_url_path)
+                .collect();
+            if !files.is_empty() {
+                unwrap_log!(WebViewAction::Reload(files).perform(&proxy));
+            }
+        }
+    });
+    Ok(())
+}
+
+fn restart_globs(dir: &Path, globs: &[String]) -> Result<Override, EmbedError> {
+    let mut overrides = OverrideBuilder::new(dir);
+    for glob in globs {
+        overrides
+            .add(glob)
+            .map_err(|e| EmbedError(format!("Invalid glob {glob}: {e}")))?;
+    }
+    overrides
+        .build()
+        .map_err(|e| EmbedError(format!("Invalid globs: {e}")))
+}
+
+fn add_paths(event: notify::Result<Event>, changed: &mut HashSet<PathBuf>) {
+    let Ok(event) = event else {
+        return;
+    };
+    if matches!(
+        event.kind,
+        EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+    ) {
+        changed.extend(event.paths);
+    }
+}
+
+/**
+ * Files are served by their url path, which always uses forward slashes
+ */
+fn to_url_path(path: &Path) -> String {
+    let parts: Vec<_> = path
+        .components()
+        .map(|part| part.as_os_str().to_string_lossy())
+        .collect();
+    parts.join("/")
+}
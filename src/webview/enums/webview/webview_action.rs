@@ -11,6 +11,14 @@ pub enum WebViewAction {
     Message(Target, i32, WindowMessage),
     Window(Target, WindowAction),
     Request(Target, RequestInfo),
+    /**
+     * Reloads the windows that loaded any of these files
+     */
+    Reload(Vec<String>),
+    /**
+     * Starts the backend again, keeping the windows open
+     */
+    Restart,
 }
 
 impl WebViewAction {
@@ -4,4 +4,5 @@ pub mod script;
 pub mod serve;
 pub mod std;
 pub mod transpile;
+pub mod vfs;
 pub mod webview_manager;
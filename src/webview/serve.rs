@@ -1,12 +1,21 @@
-use std::{borrow::Cow, fs::read, path::PathBuf};
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    path::Path,
+    sync::{Arc, Mutex},
+};
 
-use crate::webview::transpile::transpile;
+use crate::webview::{transpile::transpile, vfs::Vfs};
 use anyhow::{anyhow, ensure, Result};
 use mime_guess;
 use wry::http::{Request, Response};
 
 pub struct ResolverInfo {
-    pub root: PathBuf,
+    pub root: Arc<dyn Vfs>,
+    /**
+     * Every file requested by the window, to know which windows to reload
+     */
+    pub served: Arc<Mutex<HashSet<String>>>,
 }
 
 pub fn serve(
@@ -31,17 +40,18 @@ pub fn serve(
     } else {
         &path_string
     };
-    let path_buf = resolve.root.join(real_path);
+    resolve.served.lock().unwrap().insert(real_path.to_string());
+    let data = resolve.root.read(real_path)?;
 
-    let ext = path_buf.extension();
+    let ext = Path::new(real_path).extension();
 
     let (content, mime) = if ext.is_some() && ext.unwrap() == "ts" {
-        let data = transpile(&path_buf).map_err(|e| anyhow!("{}: {}", e, path))?;
+        let data = transpile(real_path, data).map_err(|e| anyhow!("{}: {}", e, path))?;
         (data, "text/javascript".to_owned())
     } else {
         let guess = mime_guess::from_path(path);
         let mime_type = guess.first_or_text_plain();
-        (read(path_buf)?, mime_type.to_string())
+        (data, mime_type.to_string())
     };
     Response::builder()
         .header("Content-Type", mime)
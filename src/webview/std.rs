@@ -1,4 +1,5 @@
 use crate::{
+    backend::{Backend, ChildErr, ChildOut},
     state::STATE,
     unwrap_log,
     webview::enums::{
@@ -8,17 +9,19 @@ use crate::{
 };
 use anyhow::Result;
 use std::{
-    io::{BufRead, Read, Write},
+    io::{BufRead, Write},
     thread,
 };
 use tao::event_loop::EventLoopProxy;
 
 static PREFIX: &str = "DEUTRON_IPC:";
 
-pub fn listen_backendout(proxy: EventLoopProxy<WebViewAction>) {
+/**
+ * The listener owns the output, so a restart never waits on a pipe an orphaned child keeps open
+ */
+pub fn listen_backendout(proxy: EventLoopProxy<WebViewAction>, backend_out: ChildOut) {
     thread::spawn(move || {
-        let mut backend_out = STATE.backend_out.lock().unwrap();
-        for line_result in backend_out.by_ref().lines() {
+        for line_result in backend_out.lines() {
             let mut line = unwrap_log!(line_result);
             if !line.starts_with(PREFIX) {
                 println!("{}", line);
@@ -35,10 +38,9 @@ pub fn listen_backendout(proxy: EventLoopProxy<WebViewAction>) {
     });
 }
 
-pub fn listen_backenderr(proxy: EventLoopProxy<WebViewAction>) {
+pub fn listen_backenderr(proxy: EventLoopProxy<WebViewAction>, backend_err: ChildErr) {
     thread::spawn(move || {
-        let mut backend_out = STATE.backend_err.lock().unwrap();
-        for line_result in backend_out.by_ref().lines() {
+        for line_result in backend_err.lines() {
             let mut line = unwrap_log!(line_result);
             if !line.starts_with(PREFIX) {
                 println!("{}", line);
@@ -55,6 +57,37 @@ pub fn listen_backenderr(proxy: EventLoopProxy<WebViewAction>) {
     });
 }
 
+/**
+ * Replaces the backend with a new process, the old listeners stop once its output closes
+ */
+pub fn restart_backend(proxy: EventLoopProxy<WebViewAction>) {
+    thread::spawn(move || {
+        // Messages sent meanwhile wait for the new process instead of hitting the closed pipe
+        let mut backend_in = STATE.backend_in.lock().unwrap();
+        let (backend_out, backend_err) = {
+            let mut backend = STATE.backend.lock().unwrap();
+            backend.process.kill().ok();
+            // Not logged, sending the error would wait on backend_in held here
+            backend.process.wait().ok();
+            *backend = Backend::new(
+                STATE.command.clone(),
+                &STATE.backend_cwd,
+                &STATE.backend_env,
+            );
+            *backend_in = backend.get_stdin();
+            (backend.get_stdout(), backend.get_stderr())
+        };
+        drop(backend_in);
+        if STATE.verbose {
+            println!("Restarted: {}", STATE.command.join(" "));
+        }
+        listen_backendout(proxy.clone(), backend_out);
+        listen_backenderr(proxy, backend_err);
+        let dir = STATE.backend_dir.clone().into_os_string().into_string();
+        unwrap_log!(BackendMessage::Ready(dir.unwrap()).send());
+    });
+}
+
 pub fn send_backendin(message: String) -> Result<()> {
     let mut backend_in = STATE.backend_in.lock().unwrap();
 
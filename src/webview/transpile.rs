@@ -1,7 +1,5 @@
-use std::path::PathBuf;
-
 use swc_core::common::{
-    comments::SingleThreadedComments, sync::Lrc, Globals, Mark, SourceMap, GLOBALS,
+    comments::SingleThreadedComments, sync::Lrc, FileName, Globals, Mark, SourceMap, GLOBALS,
 };
 use swc_core::ecma::ast::{Pass, Program};
 use swc_core::ecma::codegen::{text_writer::JsWriter, Config, Emitter};
@@ -12,10 +10,11 @@ use swc_core::ecma::visit::VisitMutWith;
 
 use anyhow::{bail, Context, Result};
 
-pub fn transpile(path: &PathBuf) -> Result<Vec<u8>> {
+pub fn transpile(path: &str, source: Vec<u8>) -> Result<Vec<u8>> {
     let cm: Lrc<SourceMap> = Default::default();
 
-    let fm = cm.load_file(path).context("File Not Found")?;
+    let source = String::from_utf8(source).context("File is not valid UTF-8")?;
+    let fm = cm.new_source_file(Lrc::new(FileName::Custom(path.to_owned())), source);
 
     let comments = SingleThreadedComments::default();
 
@@ -0,0 +1,201 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{Read, Seek, SeekFrom},
+    path::PathBuf,
+    sync::Mutex,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use tar::{Archive, EntryType};
+
+use crate::{
+    app::read_pack,
+    shared::payload::{Compression, PackInfo},
+};
+
+/**
+ * Where the files served to the webviews come from
+ */
+pub trait Vfs: Send + Sync {
+    fn read(&self, path: &str) -> Result<Vec<u8>>;
+}
+
+pub struct DiskFs(pub PathBuf);
+
+impl Vfs for DiskFs {
+    fn read(&self, path: &str) -> Result<Vec<u8>> {
+        fs::read(self.0.join(path)).with_context(|| format!("File not found: {path}"))
+    }
+}
+
+/**
+ * Serves a pack straight from the executable, so its files never touch the disk.
+ * Only the position of every file in the archive is kept, besides a compressed pack
+ */
+pub struct ArchiveFs {
+    source: Source,
+    entries: HashMap<String, (u64, u64)>,
+}
+
+enum Source {
+    /**
+     * The executable and the offset of the pack in it
+     */
+    Exe(Mutex<File>, u64),
+    /**
+     * A compressed stream can't seek, so it's decompressed once when the app starts
+     */
+    Memory(Vec<u8>),
+}
+
+impl ArchiveFs {
+    pub fn new(exe: File, pack: PackInfo) -> Result<ArchiveFs> {
+        let mut reader = read_pack(&exe, &pack).map_err(|e| anyhow!(e.0))?;
+        let (source, entries) = match pack.compression {
+            Compression::None => {
+                let entries = ArchiveFs::index(reader)?;
+                (Source::Exe(Mutex::new(exe), pack.offset), entries)
+            }
+            Compression::Zstd(_) => {
+                let mut data = vec![];
+                reader.read_to_end(&mut data)?;
+                let entries = ArchiveFs::index(&data[..])?;
+                (Source::Memory(data), entries)
+            }
+        };
+        Ok(ArchiveFs { source, entries })
+    }
+
+    fn index(reader: impl Read) -> Result<HashMap<String, (u64, u64)>> {
+        let mut entries = HashMap::new();
+        let mut archive = Archive::new(reader);
+        for entry in archive.entries()? {
+            let entry = entry?;
+            if entry.header().entry_type() != EntryType::Regular {
+                continue;
+            }
+            let path = entry.path()?.to_string_lossy().to_string();
+            entries.insert(path, (entry.raw_file_position(), entry.size()));
+        }
+        Ok(entries)
+    }
+}
+
+impl Vfs for ArchiveFs {
+    fn read(&self, path: &str) -> Result<Vec<u8>> {
+        let &(position, size) = self
+            .entries
+            .get(path)
+            .with_context(|| format!("File not found: {path}"))?;
+        match &self.source {
+            Source::Exe(exe, offset) => {
+                let mut data = vec![0; size as usize];
+                let mut exe = exe.lock().unwrap();
+                exe.seek(SeekFrom::Start(offset + position))?;
+                exe.read_exact(&mut data)?;
+                Ok(data)
+            }
+            Source::Memory(data) => {
+                let start = position as usize;
+                Ok(data[start..start + size as usize].to_vec())
+            }
+        }
+    }
+}
+
+/**
+ * Reads from the first layer that has the file
+ */
+pub struct LayeredFs(pub Vec<Box<dyn Vfs>>);
+
+impl Vfs for LayeredFs {
+    fn read(&self, path: &str) -> Result<Vec<u8>> {
+        for layer in &self.0 {
+            if let Ok(data) = layer.read(path) {
+                return Ok(data);
+            }
+        }
+        bail!("File not found: {path}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env,
+        fs::{self, File},
+        path::PathBuf,
+    };
+
+    use tar::{Builder, Header};
+
+    use super::{ArchiveFs, DiskFs, LayeredFs, Vfs};
+    use crate::shared::payload::{Compression, PackInfo};
+
+    fn tar(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut ar = Builder::new(vec![]);
+        for (path, data) in files {
+            let mut header = Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            ar.append_data(&mut header, path, data.as_bytes()).unwrap();
+        }
+        ar.into_inner().unwrap()
+    }
+
+    /**
+     * A pack behind some runtime bytes, like in a compiled binary
+     */
+    fn archive(name: &str, compression: Compression) -> ArchiveFs {
+        let tar = tar(&[("index.html", "<html>"), ("js/app.js", "app()")]);
+        let data = match compression {
+            Compression::None => tar,
+            Compression::Zstd(level) => zstd::encode_all(&tar[..], level).unwrap(),
+        };
+        let path = temp_path(name);
+        fs::write(&path, [b"runtime".as_slice(), &data].concat()).unwrap();
+        let exe = File::open(&path).unwrap();
+        fs::remove_file(&path).ok();
+        let pack = PackInfo {
+            offset: 7,
+            len: data.len() as u64,
+            compression,
+            hash: String::new(),
+            files: vec![],
+        };
+        ArchiveFs::new(exe, pack).unwrap()
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("deutron-test-{}-{name}", std::process::id()))
+    }
+
+    #[test]
+    fn archive_files() {
+        for (name, compression) in [("plain", Compression::None), ("zstd", Compression::Zstd(3))] {
+            let files = archive(name, compression);
+            assert_eq!(files.read("index.html").unwrap(), b"<html>");
+            assert_eq!(files.read("js/app.js").unwrap(), b"app()");
+            assert!(files.read("js").is_err());
+            assert!(files.read("missing.js").is_err());
+        }
+    }
+
+    #[test]
+    fn first_layer_wins() {
+        let dir = temp_path("layers");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("index.html"), "<disk>").unwrap();
+        fs::write(dir.join("disk.txt"), "disk").unwrap();
+        let files = LayeredFs(vec![
+            Box::new(archive("layered", Compression::None)),
+            Box::new(DiskFs(dir.clone())),
+        ]);
+
+        assert_eq!(files.read("index.html").unwrap(), b"<html>");
+        assert_eq!(files.read("disk.txt").unwrap(), b"disk");
+        assert!(files.read("missing.js").is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,13 +1,21 @@
 use rand::rngs::ThreadRng;
 use rand::Rng;
-use std::{borrow::Cow, collections::HashMap};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
 
 use tao::{
     event::{Event, StartCause, WindowEvent as WryWindowEvent},
     event_loop::{ControlFlow, EventLoopBuilder, EventLoopProxy, EventLoopWindowTarget},
     window::{Window, WindowBuilder, WindowId},
 };
-use wry::{dpi::LogicalSize, http::Request, WebContext, WebView, WebViewBuilder};
+use wry::{
+    dpi::LogicalSize,
+    http::{Request, Uri},
+    WebContext, WebView, WebViewBuilder,
+};
 
 use crate::{
     cleanup::cleanup_backend,
@@ -24,7 +32,7 @@ use crate::{
         },
         icon::load_icon,
         serve::{serve, ResolverInfo},
-        std::{listen_backenderr, listen_backendout},
+        std::{listen_backenderr, listen_backendout, restart_backend},
     },
 };
 
@@ -46,6 +54,7 @@ impl WindowManagerId {
 pub struct WebViewExt {
     pub view: WebView,
     pub window: Window,
+    pub served: Arc<Mutex<HashSet<String>>>,
 }
 
 pub struct WebViewManager {
@@ -104,11 +113,46 @@ impl WebViewManager {
         self.ids.iter()
     }
 
+    /**
+     * Reloads the windows that requested any of the changed files
+     */
+    pub fn reload(&self, changed: &[String]) -> wry::Result<()> {
+        for webview in self.webviews.values() {
+            let affected = {
+                let mut served = webview.served.lock().unwrap();
+                let affected = changed.iter().any(|file| served.contains(file));
+                if affected {
+                    served.clear();
+                }
+                affected
+            };
+            if affected {
+                webview.view.reload()?;
+            }
+        }
+        Ok(())
+    }
+
     pub fn iter_webviews(&self) -> std::collections::hash_map::Iter<'_, i32, WebViewExt> {
         self.webviews.iter()
     }
 }
 
+/**
+ * Pages can only navigate within the packed files, or the dev server of --frontend-url
+ */
+fn is_allowed(url: &str) -> bool {
+    let origin = |url: &str| {
+        let uri: Uri = url.parse().ok()?;
+        Some(format!("{}://{}", uri.scheme_str()?, uri.authority()?))
+    };
+    url.starts_with("http://local.files/")
+        || STATE
+            .frontend_url
+            .as_deref()
+            .is_some_and(|frontend_url| origin(frontend_url) == origin(url))
+}
+
 /**
  * Wry functions
  */
@@ -120,8 +164,10 @@ impl WebViewManager {
         event_loop: &EventLoopWindowTarget<WebViewAction>,
         context: &mut WebContext,
     ) -> wry::Result<WindowManagerId> {
+        let served = Arc::new(Mutex::new(HashSet::new()));
         let resolve = ResolverInfo {
-            root: STATE.backend_dir.clone(),
+            root: STATE.files.clone(),
+            served: served.clone(),
         };
         let id = self.gen_id();
         let handler = move |req: Request<String>| {
@@ -171,6 +217,7 @@ impl WebViewManager {
             .unwrap();
 
         let window_id = window.id();
+        let root = STATE.frontend_url.as_deref().unwrap_or("local://files/");
         let webview = WebViewBuilder::new_with_web_context(context)
             .with_custom_protocol("local".to_string(), move |_id, request| {
                 let response = match serve(&request, &resolve) {
@@ -187,9 +234,9 @@ impl WebViewManager {
                 };
                 response
             })
-            .with_navigation_handler(|url| url.starts_with("http://local.files/"))
-            .with_new_window_req_handler(|url| url.starts_with("http://local.files/"))
-            .with_url(&format!("local://files/{}", window_config.url))
+            .with_navigation_handler(|url| is_allowed(&url))
+            .with_new_window_req_handler(|url| is_allowed(&url))
+            .with_url(&format!("{root}{}", window_config.url))
             .with_ipc_handler(handler)
             .with_initialization_script(script)
             .with_transparent(transparent)
@@ -202,17 +249,30 @@ impl WebViewManager {
             WebViewExt {
                 view: webview,
                 window,
+                served,
             },
         );
         Ok(WindowManagerId::Number(id))
     }
 
-    pub fn start(mut self, actions: Option<Vec<WebViewAction>>) {
+    /**
+     * Runs the event loop, on_start receives a proxy to send actions from other threads
+     */
+    pub fn start(
+        mut self,
+        actions: Option<Vec<WebViewAction>>,
+        on_start: impl FnOnce(EventLoopProxy<WebViewAction>),
+    ) {
         let event_loop = EventLoopBuilder::<WebViewAction>::with_user_event().build();
         let proxy = event_loop.create_proxy();
+        on_start(proxy.clone());
         let mut context = WebContext::new(Some(STATE.webview_dir.clone()));
-        listen_backendout(proxy.clone());
-        listen_backenderr(proxy.clone());
+        if let Some(backend_out) = STATE.backend_out.lock().unwrap().take() {
+            listen_backendout(proxy.clone(), backend_out);
+        }
+        if let Some(backend_err) = STATE.backend_err.lock().unwrap().take() {
+            listen_backenderr(proxy.clone(), backend_err);
+        }
         for action in actions.unwrap_or(vec![]) {
             unwrap_log!(action.perform(&proxy));
         }
@@ -280,6 +340,10 @@ impl WebViewManager {
             WebViewAction::Request(source, request) => {
                 request.perform(source, self, loop_vars.proxy)
             }
+            WebViewAction::Reload(changed) => {
+                unwrap_log!(self.reload(&changed));
+            }
+            WebViewAction::Restart => restart_backend(loop_vars.proxy.clone()),
         }
     }
 }